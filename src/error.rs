@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::fmt;
+
+/// Everything that can go wrong while creating a window.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowError {
+    /// Couldn't connect to the display server.
+    NoDisplay,
+    /// The display server doesn't support GLX at all.
+    NoGlx,
+    /// The GLX version is too old (we need at least 1.3). Stored as `major * 10 + minor`.
+    GlxVersionTooOld(i32),
+    /// A required GLX extension is missing.
+    MissingGlxExtension(&'static str),
+    /// A required GL/GLX function couldn't be loaded.
+    MissingGlFunction(String),
+    /// No framebuffer config matched the requested visual attributes.
+    NoMatchingFbConfig,
+    /// The GL context couldn't be created.
+    ContextCreationFailed,
+    /// We got a GL context, but it's an indirect rendering context.
+    IndirectContext,
+    /// Setting up EGL failed.
+    Egl(String),
+    /// The software backend can't draw to a window of this depth (it needs 24 or 32 bits).
+    UnsupportedVisual(u8),
+    /// Couldn't intern an X atom.
+    AtomFailed(String),
+    /// A system call failed while setting up the event loop (e.g. creating its wake-up pipe).
+    Os(String),
+    /// The GUI thread died before it could report whether window creation succeeded.
+    ThreadDied,
+    /// The GUI thread didn't get to the window in time, e.g. because another window's `GuiState`
+    /// is stuck in a callback.
+    ThreadTimedOut,
+    /// The X server rejected one of our requests.
+    X(XError),
+}
+
+/// An error the X server sent in reply to one of our requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XError {
+    /// What we were doing, e.g. `"CreateWindow"` or `"glXCreateContextAttribsARB"`.
+    pub request: &'static str,
+    /// The error code, e.g. 8 for `BadMatch`. Codes above 127 belong to extensions.
+    pub error_code: u8,
+    /// The failed request's major opcode. Opcodes above 127 belong to extensions.
+    pub major_code: u8,
+    /// The failed request's minor opcode, for extension requests.
+    pub minor_code: u16,
+    /// The resource (window, colormap, ...) the error is about, if any.
+    pub resource_id: u32,
+}
+
+impl XError {
+    /// The core protocol's name for the error code, like `"BadWindow"`.
+    pub fn error_name(&self) -> Option<&'static str> {
+        let name = match self.error_code {
+            1 => "BadRequest",
+            2 => "BadValue",
+            3 => "BadWindow",
+            4 => "BadPixmap",
+            5 => "BadAtom",
+            6 => "BadCursor",
+            7 => "BadFont",
+            8 => "BadMatch",
+            9 => "BadDrawable",
+            10 => "BadAccess",
+            11 => "BadAlloc",
+            12 => "BadColor",
+            13 => "BadGC",
+            14 => "BadIDChoice",
+            15 => "BadName",
+            16 => "BadLength",
+            17 => "BadImplementation",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for XError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error_name() {
+            Some(name) => write!(f, "{}", name)?,
+            None => write!(f, "extension error {}", self.error_code)?,
+        }
+        write!(
+            f,
+            " from {} (opcode {}.{}, resource 0x{:x})",
+            self.request, self.major_code, self.minor_code, self.resource_id
+        )
+    }
+}
+
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowError::NoDisplay => write!(f, "could not connect to the display"),
+            WindowError::NoGlx => write!(f, "display does not support GLX"),
+            WindowError::GlxVersionTooOld(version) => write!(
+                f,
+                "GLX version {}.{} is too old (need at least 1.3)",
+                version / 10,
+                version % 10
+            ),
+            WindowError::MissingGlxExtension(name) => {
+                write!(f, "could not find GLX extension {}", name)
+            }
+            WindowError::MissingGlFunction(name) => write!(f, "could not load {}", name),
+            WindowError::NoMatchingFbConfig => write!(f, "could not find compatible fb config"),
+            WindowError::ContextCreationFailed => write!(f, "error when creating gl context"),
+            WindowError::IndirectContext => write!(f, "obtained indirect rendering context"),
+            WindowError::Egl(message) => write!(f, "EGL error: {}", message),
+            WindowError::UnsupportedVisual(depth) => {
+                write!(f, "unsupported visual depth {} for software rendering", depth)
+            }
+            WindowError::AtomFailed(name) => write!(f, "could not load atom for {}", name),
+            WindowError::Os(message) => write!(f, "system error: {}", message),
+            WindowError::ThreadDied => write!(f, "GUI thread died during window creation"),
+            WindowError::ThreadTimedOut => write!(f, "GUI thread did not respond in time"),
+            WindowError::X(error) => write!(f, "X error: {}", error),
+        }
+    }
+}
+
+impl Error for WindowError {}
+
+impl From<XError> for WindowError {
+    fn from(error: XError) -> Self {
+        WindowError::X(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x_errors_are_decoded() {
+        let mut error = XError {
+            request: "CreateWindow",
+            error_code: 8,
+            major_code: 1,
+            minor_code: 0,
+            resource_id: 0x400001,
+        };
+        assert_eq!(
+            WindowError::X(error.clone()).to_string(),
+            "X error: BadMatch from CreateWindow (opcode 1.0, resource 0x400001)"
+        );
+        error.error_code = 160;
+        assert_eq!(error.error_name(), None);
+        assert!(error.to_string().starts_with("extension error 160 from"));
+    }
+}
@@ -1,3 +1,9 @@
+use std::any::Any;
+use std::time::{Duration, Instant};
+
+use crate::pixel_buffer::PixelBuffer;
+use crate::window::RawWindow;
+
 // TODO: move somewhere else
 pub enum MouseEvent {
     LeftMouseButtonDown,
@@ -9,9 +15,140 @@ pub enum MouseEvent {
     ScrollRight,
     BackMouseButtonDown,
     ForwardMouseButtonDown,
+    LeftMouseButtonUp,
+    MiddleMouseButtonUp,
+    RightMouseButtonUp,
+    BackMouseButtonUp,
+    ForwardMouseButtonUp,
+    /// The pointer moved inside the window.
+    Motion,
+    /// The pointer entered the window.
+    Enter,
+    /// The pointer left the window.
+    Leave,
+}
+
+/// The modifier keys that were held down when an event happened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub super_key: bool,
+}
+
+/// A key was pressed or released.
+pub struct KeyEvent {
+    /// `true` for a key press, `false` for a release.
+    pub pressed: bool,
+    /// The raw (hardware) keycode.
+    pub keycode: u32,
+    /// The keysym for this key in the current layout, e.g. `x11::keysym::XK_Return`.
+    pub keysym: u32,
+    /// The text this key produces, if any. `None` for keys like Shift, arrows or Return.
+    pub text: Option<String>,
+    pub modifiers: Modifiers,
+    /// `true` if this press was generated by the key being held down (auto-repeat).
+    pub repeat: bool,
+    /// The display server's timestamp for the event, in milliseconds.
+    pub time: u32,
+}
+
+/// Timing for the frame being drawn, for animations.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// When the frame started. Monotonic, so frames can be compared with each other.
+    pub time: Instant,
+    /// Time since the previous frame, or zero for the first one. This includes any time the
+    /// window spent hidden.
+    pub delta: Duration,
+}
+
+/// What the driver actually gave us, passed to `GuiState::gl_init()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlInfo {
+    /// The context's version as (major, minor). For OpenGL ES contexts, it's the ES version.
+    pub version: (u32, u32),
+    /// The full `GL_VERSION` string, e.g. "4.6 (Compatibility Profile) Mesa 23.2.1".
+    pub version_string: String,
+    pub renderer: String,
+    pub vendor: String,
+    pub extensions: Vec<String>,
+}
+
+impl GlInfo {
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|extension| extension == name)
+    }
 }
 
 pub trait GuiState: std::marker::Send {
-    fn draw(&mut self);
-    fn handle_mouse(&mut self, mouse_event: MouseEvent, x: i32, y: i32);
-}
\ No newline at end of file
+    /// Called once after the GL context is created, with it current, before anything else is
+    /// drawn. Compile shaders and upload textures here. Not called with the software backend.
+    ///
+    /// `gl` holds the functions loaded for this window's context. It's the same one for every
+    /// call, so it's fine to keep objects created with it around until `gl_destroy()`.
+    fn gl_init(&mut self, _gl: &glow::Context, _info: &GlInfo) {}
+
+    /// Called once before the GL context is destroyed, with it current. Delete your GL objects
+    /// here. Not called with the software backend.
+    fn gl_destroy(&mut self, _gl: &glow::Context) {}
+
+    /// Draw with OpenGL, through `gl`. The window's GL context is current, and the buffers are
+    /// swapped after this returns.
+    fn draw(&mut self, gl: &glow::Context, frame: &Frame);
+
+    /// Draw with the software backend (see `WindowOptions::backend`). `buffer` is the size of the
+    /// window, in pixels, and is shown after this returns. Its contents are undefined, so paint
+    /// every pixel.
+    fn draw_software(&mut self, _buffer: &mut PixelBuffer, _frame: &Frame) {}
+
+    /// With `Backend::None`, called once after the window is created, before anything is drawn.
+    /// Set up your renderer for `window` here (e.g. create the wgpu surface).
+    fn raw_init(&mut self, _window: &RawWindow) {}
+
+    /// With `Backend::None`, called once before the window is destroyed. Drop everything that
+    /// refers to it here.
+    fn raw_destroy(&mut self) {}
+
+    /// Draw with your own renderer (see `Backend::None`). Presenting the frame is up to you.
+    fn draw_raw(&mut self, _window: &RawWindow, _frame: &Frame) {}
+
+    /// `x` and `y` are relative to the window's top-left corner, in logical units if
+    /// `WindowOptions::logical_units` is set and in pixels otherwise. `time` is the display server's
+    /// timestamp for the event, in milliseconds; use it (not the wall clock) for drag gestures
+    /// and double clicks.
+    fn handle_mouse(&mut self, mouse_event: MouseEvent, x: i32, y: i32, modifiers: Modifiers, time: u32);
+
+    /// Called for key presses and releases while the window has keyboard focus (which it takes
+    /// when clicked, with `WindowOptions::take_focus`), and for the ones the host passes on
+    /// through `Window::handle_host_key()`.
+    fn handle_keyboard(&mut self, _key_event: KeyEvent) {}
+
+    /// Called when the window's size changed, e.g. because the host resized the editor. A redraw
+    /// follows, with the GL viewport already set to the new size.
+    fn resized(&mut self, _width: u32, _height: u32) {}
+
+    /// Called once when the window is created, and again whenever the scale factor changes (e.g.
+    /// the window moved to a monitor with a different DPI). 1.0 means 96 DPI. A redraw follows.
+    fn scale_factor_changed(&mut self, _scale_factor: f64) {}
+
+    /// Called when the window manager asks to close the window, e.g. because the user clicked
+    /// its close button (only top-level windows have one; hosts close editors themselves). The
+    /// window stays open until the `Window` is dropped; hide it or tell the host, as fits.
+    fn close_requested(&mut self) {}
+
+    /// Called when a timer set with `Window::set_timer()` (or one of its variants) fires.
+    fn timer(&mut self, _id: u64) {}
+
+    /// Called with each message posted through a `WindowProxy`, in the order they were posted.
+    /// Downcast it to whatever type you sent, e.g. `message.downcast::<MyMessage>()`.
+    fn handle_message(&mut self, _message: Box<dyn Any + Send>) {}
+
+    /// Called with the latest value of each parameter that changed in the `ParameterQueue`
+    /// attached through `WindowOptions::parameters`. A redraw follows.
+    fn parameter_changed(&mut self, _index: usize, _value: f32) {}
+
+    /// Called with the peak level each meter reached since the last frame. A redraw follows.
+    fn meter_changed(&mut self, _index: usize, _level: f32) {}
+}
@@ -1,6 +1,16 @@
 extern crate log;
 
+/// The GL bindings `GuiState` gets. Re-exported so you draw with the same version we load.
+pub use glow;
+
 mod platform;
 
+pub mod error;
 pub mod window;
-pub mod gui_state;
\ No newline at end of file
+pub mod gui_state;
+pub mod parameters;
+pub mod pixel_buffer;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "vst")]
+pub mod vst_editor;
\ No newline at end of file
@@ -0,0 +1,171 @@
+// A lock-free, allocation-free way for the audio thread to tell the editor about parameter values
+// and meter levels.
+//
+// Every parameter and meter has a fixed slot holding an `f32` (as bits in an `AtomicU32`), plus a
+// "dirty" bit. The audio thread only ever stores into slots and sets bits; the GUI thread picks up
+// whatever's dirty once per frame. Nothing is queued, so the audio thread can't fill anything up:
+// for parameters the latest value wins, and meters keep the peak since the GUI last looked.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// One change picked up by `ParameterQueue::drain()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterChange {
+    /// The latest value of parameter `index`.
+    Parameter { index: usize, value: f32 },
+    /// The highest level meter `index` reached since the last drain.
+    Meter { index: usize, level: f32 },
+}
+
+/// Parameter values and meter levels on their way from the audio thread to the GUI.
+///
+/// Create it once with the number of parameters and meters, share it in an `Arc`, and attach it
+/// to the window through `WindowOptions::parameters`. The window then drains it once per frame and
+/// passes the changes to `GuiState::parameter_changed()` / `GuiState::meter_changed()`.
+///
+/// `set_parameter()` and `set_meter()` never lock, allocate or make system calls, so they're
+/// safe to call from `process()`.
+pub struct ParameterQueue {
+    parameters: Box<[AtomicU32]>,
+    parameters_dirty: Box<[AtomicU64]>,
+    meters: Box<[AtomicU32]>,
+    meters_dirty: Box<[AtomicU64]>,
+}
+
+impl ParameterQueue {
+    pub fn new(num_parameters: usize, num_meters: usize) -> Self {
+        Self {
+            parameters: slots(num_parameters),
+            parameters_dirty: dirty_bits(num_parameters),
+            meters: slots(num_meters),
+            meters_dirty: dirty_bits(num_meters),
+        }
+    }
+
+    pub fn num_parameters(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub fn num_meters(&self) -> usize {
+        self.meters.len()
+    }
+
+    /// Report a new value for parameter `index`. Out of range indices are ignored.
+    pub fn set_parameter(&self, index: usize, value: f32) {
+        if let Some(slot) = self.parameters.get(index) {
+            slot.store(value.to_bits(), Ordering::Relaxed);
+            // Release: whoever sees the bit also sees the value (or a newer one).
+            mark_dirty(&self.parameters_dirty, index);
+        }
+    }
+
+    /// Report a meter level. The GUI gets the highest level since it last looked. Levels should
+    /// be non-negative; anything below zero (or NaN) counts as zero. Out of range indices are
+    /// ignored.
+    pub fn set_meter(&self, index: usize, level: f32) {
+        if let Some(slot) = self.meters.get(index) {
+            let level = if level > 0.0 { level } else { 0.0 };
+            // Non-negative floats sort the same way as their bit patterns, so an integer max
+            // gives us the float max without a compare-and-swap loop.
+            slot.fetch_max(level.to_bits(), Ordering::Relaxed);
+            mark_dirty(&self.meters_dirty, index);
+        }
+    }
+
+    /// Take everything that changed since the last call, calling `f` with each change. Meters are
+    /// reset to zero as they're read. Meant for the GUI thread; don't drain from more than one
+    /// thread at a time, or the changes will be split between them.
+    pub fn drain<F: FnMut(ParameterChange)>(&self, mut f: F) {
+        for_each_dirty(&self.parameters_dirty, |index| {
+            let value = f32::from_bits(self.parameters[index].load(Ordering::Relaxed));
+            f(ParameterChange::Parameter { index, value });
+        });
+        for_each_dirty(&self.meters_dirty, |index| {
+            let level = f32::from_bits(self.meters[index].swap(0, Ordering::Relaxed));
+            f(ParameterChange::Meter { index, level });
+        });
+    }
+}
+
+impl fmt::Debug for ParameterQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParameterQueue")
+            .field("num_parameters", &self.num_parameters())
+            .field("num_meters", &self.num_meters())
+            .finish()
+    }
+}
+
+fn slots(count: usize) -> Box<[AtomicU32]> {
+    (0..count).map(|_| AtomicU32::new(0)).collect()
+}
+
+fn dirty_bits(count: usize) -> Box<[AtomicU64]> {
+    (0..count.div_ceil(64)).map(|_| AtomicU64::new(0)).collect()
+}
+
+fn mark_dirty(dirty: &[AtomicU64], index: usize) {
+    dirty[index / 64].fetch_or(1 << (index % 64), Ordering::Release);
+}
+
+fn for_each_dirty<F: FnMut(usize)>(dirty: &[AtomicU64], mut f: F) {
+    for (word_index, word) in dirty.iter().enumerate() {
+        // Acquire pairs with the Release in mark_dirty().
+        let mut bits = word.swap(0, Ordering::Acquire);
+        while bits != 0 {
+            let bit = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            f(word_index * 64 + bit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drained(queue: &ParameterQueue) -> Vec<ParameterChange> {
+        let mut changes = Vec::new();
+        queue.drain(|change| changes.push(change));
+        changes
+    }
+
+    #[test]
+    fn parameters_are_coalesced() {
+        let queue = ParameterQueue::new(100, 0);
+        queue.set_parameter(70, 0.25);
+        queue.set_parameter(3, 0.5);
+        queue.set_parameter(70, 0.75);
+        queue.set_parameter(100, 1.0);
+
+        assert_eq!(
+            drained(&queue),
+            vec![
+                ParameterChange::Parameter { index: 3, value: 0.5 },
+                ParameterChange::Parameter { index: 70, value: 0.75 },
+            ]
+        );
+        assert!(drained(&queue).is_empty());
+    }
+
+    #[test]
+    fn meters_keep_the_peak() {
+        let queue = ParameterQueue::new(0, 2);
+        queue.set_meter(1, 0.5);
+        queue.set_meter(1, 0.9);
+        queue.set_meter(1, 0.1);
+        queue.set_meter(0, -1.0);
+
+        assert_eq!(
+            drained(&queue),
+            vec![
+                ParameterChange::Meter { index: 0, level: 0.0 },
+                ParameterChange::Meter { index: 1, level: 0.9 },
+            ]
+        );
+
+        queue.set_meter(1, 0.2);
+        assert_eq!(drained(&queue), vec![ParameterChange::Meter { index: 1, level: 0.2 }]);
+    }
+}
@@ -0,0 +1,101 @@
+// The CPU-side image the software backend hands to `GuiState::draw_software()`.
+
+/// An RGBA image, 8 bits per channel, rows top to bottom with no padding between them.
+///
+/// Alpha is ignored when the buffer is shown; the window has no transparency.
+pub struct PixelBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl PixelBuffer {
+    /// A buffer of `width` x `height` transparent black pixels.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Bytes per row.
+    pub fn stride(&self) -> usize {
+        self.width as usize * 4
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// The pixel at `(x, y)`, or `None` if that's outside the buffer.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        let offset = self.offset(x, y)?;
+        let pixel = &self.pixels[offset..offset + 4];
+        Some([pixel[0], pixel[1], pixel[2], pixel[3]])
+    }
+
+    /// Set the pixel at `(x, y)`. Does nothing if that's outside the buffer.
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        if let Some(offset) = self.offset(x, y) {
+            self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+        }
+    }
+
+    /// Set every pixel to `rgba`.
+    pub fn fill(&mut self, rgba: [u8; 4]) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+
+    /// Change the size. The contents are undefined afterwards; the next draw repaints them anyway.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.pixels.resize(width as usize * height as usize * 4, 0);
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y as usize * self.stride() + x as usize * 4)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_are_row_major_rgba() {
+        let mut buffer = PixelBuffer::new(3, 2);
+        buffer.fill([1, 2, 3, 4]);
+        buffer.set_pixel(2, 1, [9, 8, 7, 6]);
+        buffer.set_pixel(3, 0, [0xff; 4]);
+
+        assert_eq!(buffer.stride(), 12);
+        assert_eq!(buffer.pixel(0, 0), Some([1, 2, 3, 4]));
+        assert_eq!(&buffer.pixels()[20..24], &[9, 8, 7, 6]);
+        assert_eq!(buffer.pixel(3, 0), None);
+
+        buffer.resize(4, 4);
+        assert_eq!(buffer.pixels().len(), 64);
+    }
+}
@@ -0,0 +1,36 @@
+// Where the event loop gets the time from. Windows use the system clock; `MockWindow` moves its
+// own along by hand, so timers and frame pacing come out the same on every test run.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Clone, Default)]
+pub struct Clock {
+    // `None` for the system clock.
+    manual: Option<Arc<Mutex<Instant>>>,
+}
+
+impl Clock {
+    /// A clock that stands still at `start` until `advance()`d.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn manual(start: Instant) -> Self {
+        Self {
+            manual: Some(Arc::new(Mutex::new(start))),
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        match &self.manual {
+            Some(now) => *now.lock().unwrap(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Move a manual clock forward. Does nothing to the system clock.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn advance(&self, by: std::time::Duration) {
+        if let Some(now) = &self.manual {
+            *now.lock().unwrap() += by;
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// The EGL backend: an EGL context (desktop GL, or GLES 2/3) on the same kind of XCB window the
+// GLX backend uses. libEGL is loaded at runtime, so the plugin still loads on systems without it.
+
+use std::os::raw::c_void;
+use std::sync::Mutex;
+
+use khronos_egl as egl;
+use log::*;
+
+use crate::error::WindowError;
+use crate::window::{EglApi, GlConfig, GlProfile, Vsync};
+use super::x_handle::XHandle;
+
+type Egl = egl::DynamicInstance<egl::EGL1_4>;
+
+/// What `setup_egl()` picks before the window exists. The window has to be created with
+/// `visual_id`/`depth`, then `EglRenderer::new()` puts a surface on it. Dropping this destroys the
+/// context.
+pub struct EglSetup {
+    pub visual_id: u32,
+    pub depth: u8,
+    egl: Egl,
+    display: egl::Display,
+    config: egl::Config,
+    context: egl::Context,
+    srgb: bool,
+}
+
+/// How many windows use each initialized EGL display. Threaded windows all share one X display,
+/// and so one EGL display, but eglTerminate() isn't reference counted.
+static DISPLAY_USERS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+fn initialize(egl: &Egl, display: egl::Display) -> Result<(i32, i32), WindowError> {
+    let mut users = DISPLAY_USERS.lock().unwrap();
+    let version = egl.initialize(display).map_err(egl_error("eglInitialize"))?;
+    let key = display.as_ptr() as usize;
+    match users.iter_mut().find(|(display, _)| *display == key) {
+        Some((_, count)) => *count += 1,
+        None => users.push((key, 1)),
+    }
+    Ok(version)
+}
+
+/// Terminate `display` once the last window using it is done with it.
+fn terminate(egl: &Egl, display: egl::Display) {
+    let mut users = DISPLAY_USERS.lock().unwrap();
+    let key = display.as_ptr() as usize;
+    if let Some(index) = users.iter().position(|(display, _)| *display == key) {
+        users[index].1 -= 1;
+        if users[index].1 == 0 {
+            users.swap_remove(index);
+            let _ = egl.terminate(display);
+        }
+    }
+}
+
+pub fn setup_egl(
+    x_handle: &XHandle,
+    api: EglApi,
+    gl_config: &GlConfig,
+) -> Result<EglSetup, WindowError> {
+    let egl = unsafe { Egl::load_required() }
+        .map_err(|e| WindowError::Egl(format!("could not load libEGL: {}", e)))?;
+
+    let display = unsafe { egl.get_display(x_handle.raw_display() as *mut c_void) }
+        .ok_or_else(|| WindowError::Egl("no EGL display".to_string()))?;
+    let (major, minor) = initialize(&egl, display)?;
+    info!("EGL version {}.{}", major, minor);
+
+    // Everything after this has to terminate the display again if it fails.
+    let setup = choose_and_create(&egl, display, x_handle, api, gl_config);
+    if setup.is_err() {
+        terminate(&egl, display);
+    }
+    let (config, context, visual_id, depth) = setup?;
+
+    Ok(EglSetup {
+        visual_id,
+        depth,
+        egl,
+        display,
+        config,
+        context,
+        srgb: gl_config.srgb,
+    })
+}
+
+fn choose_and_create(
+    egl: &Egl,
+    display: egl::Display,
+    x_handle: &XHandle,
+    api: EglApi,
+    gl_config: &GlConfig,
+) -> Result<(egl::Config, egl::Context, u32, u8), WindowError> {
+    let (bind_api, renderable_type) = match api {
+        EglApi::OpenGl => (egl::OPENGL_API, egl::OPENGL_BIT),
+        EglApi::Gles2 => (egl::OPENGL_ES_API, egl::OPENGL_ES2_BIT),
+        // EGL_OPENGL_ES3_BIT_KHR; same value as the EGL 1.5 constant.
+        EglApi::Gles3 => (egl::OPENGL_ES_API, 0x0040),
+    };
+    egl.bind_api(bind_api).map_err(egl_error("eglBindAPI"))?;
+
+    #[rustfmt::skip]
+    let mut config_attributes = vec![
+        egl::SURFACE_TYPE, egl::WINDOW_BIT,
+        egl::RENDERABLE_TYPE, renderable_type,
+        egl::RED_SIZE, 8,
+        egl::GREEN_SIZE, 8,
+        egl::BLUE_SIZE, 8,
+        egl::ALPHA_SIZE, 8,
+        egl::DEPTH_SIZE, gl_config.depth_bits as egl::Int,
+        egl::STENCIL_SIZE, gl_config.stencil_bits as egl::Int,
+    ];
+    if gl_config.samples > 0 {
+        config_attributes.extend_from_slice(&[
+            egl::SAMPLE_BUFFERS, 1,
+            egl::SAMPLES, gl_config.samples as egl::Int,
+        ]);
+    }
+    config_attributes.push(egl::NONE);
+    let config = egl
+        .choose_first_config(display, &config_attributes)
+        .map_err(egl_error("eglChooseConfig"))?
+        .ok_or(WindowError::NoMatchingFbConfig)?;
+    let visual_id = egl
+        .get_config_attrib(display, config, egl::NATIVE_VISUAL_ID)
+        .map_err(egl_error("eglGetConfigAttrib"))? as u32;
+    let depth = x_handle.visual_depth(visual_id).ok_or(WindowError::NoMatchingFbConfig)?;
+
+    let context = match api {
+        EglApi::OpenGl => create_gl_context(egl, display, config, gl_config),
+        EglApi::Gles2 => {
+            let attributes = [egl::CONTEXT_CLIENT_VERSION, 2, egl::NONE];
+            egl.create_context(display, config, None, &attributes)
+        }
+        EglApi::Gles3 => {
+            let attributes = [egl::CONTEXT_CLIENT_VERSION, 3, egl::NONE];
+            egl.create_context(display, config, None, &attributes)
+        }
+    }
+    .map_err(|e| {
+        error!("eglCreateContext failed: {}", e);
+        WindowError::ContextCreationFailed
+    })?;
+
+    let needs_gl3 = gl_config.versions().all(|version| version >= (3, 0));
+    let has_gl3 = egl.get_proc_address("glGenVertexArrays").is_some();
+    if api == EglApi::OpenGl && needs_gl3 && !has_gl3 {
+        let _ = egl.destroy_context(display, context);
+        return Err(WindowError::MissingGlFunction("glGenVertexArrays".to_string()));
+    }
+
+    Ok((config, context, visual_id, depth))
+}
+
+/// A desktop GL context for `gl_config.version`, or the first of its fallbacks that works.
+fn create_gl_context(
+    egl: &Egl,
+    display: egl::Display,
+    config: egl::Config,
+    gl_config: &GlConfig,
+) -> Result<egl::Context, egl::Error> {
+    for version in gl_config.versions() {
+        let attributes = context_attributes(gl_config, version);
+        match egl.create_context(display, config, None, &attributes) {
+            Ok(context) => {
+                info!("Created an OpenGL {}.{} context", version.0, version.1);
+                return Ok(context);
+            }
+            Err(e) => {
+                warn!("Could not create an OpenGL {}.{} context: {}", version.0, version.1, e)
+            }
+        }
+    }
+    // The version attributes need EGL 1.5 or EGL_KHR_create_context; without them we take
+    // whatever version we get, and the GL 3.0 check after this sorts it out.
+    egl.create_context(display, config, None, &[egl::NONE])
+}
+
+/// The `eglCreateContext()` attributes for a desktop GL context of `version`.
+fn context_attributes(gl_config: &GlConfig, version: (u8, u8)) -> Vec<egl::Int> {
+    #[rustfmt::skip]
+    let mut attributes = vec![
+        egl::CONTEXT_MAJOR_VERSION, version.0 as egl::Int,
+        egl::CONTEXT_MINOR_VERSION, version.1 as egl::Int,
+    ];
+    if version >= (3, 2) {
+        let profile = match gl_config.profile {
+            GlProfile::Core => egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            GlProfile::Compatibility => egl::CONTEXT_OPENGL_COMPATIBILITY_PROFILE_BIT,
+        };
+        attributes.extend_from_slice(&[egl::CONTEXT_OPENGL_PROFILE_MASK, profile]);
+    }
+    if gl_config.debug {
+        attributes.extend_from_slice(&[egl::CONTEXT_OPENGL_DEBUG, egl::TRUE as egl::Int]);
+    }
+    if gl_config.forward_compatible && version >= (3, 0) {
+        let forward_compatible = egl::CONTEXT_OPENGL_FORWARD_COMPATIBLE;
+        attributes.extend_from_slice(&[forward_compatible, egl::TRUE as egl::Int]);
+    }
+    attributes.push(egl::NONE);
+    attributes
+}
+
+fn egl_error(function: &'static str) -> impl Fn(egl::Error) -> WindowError {
+    move |e| WindowError::Egl(format!("{} failed: {}", function, e))
+}
+
+impl Drop for EglSetup {
+    fn drop(&mut self) {
+        let _ = self.egl.destroy_context(self.display, self.context);
+        // The EGL display belongs to the Xlib display, which is closed along with the XHandle.
+        // Terminate it once nobody uses it, so a later display at the same address can't pick up
+        // its state.
+        terminate(&self.egl, self.display);
+    }
+}
+
+pub struct EglRenderer {
+    // Dropped after the surface is destroyed.
+    setup: EglSetup,
+    surface: egl::Surface,
+    api: EglApi,
+}
+
+impl EglRenderer {
+    pub fn new(setup: EglSetup, window_id: u32, api: EglApi) -> Result<Self, WindowError> {
+        // On X11, EGLNativeWindowType is the window's XID.
+        let native_window = window_id as usize as egl::NativeWindowType;
+        let srgb_attributes = [egl::GL_COLORSPACE, egl::GL_COLORSPACE_SRGB, egl::NONE];
+        let attributes = if setup.srgb { Some(&srgb_attributes[..]) } else { None };
+        let surface = unsafe {
+            setup.egl.create_window_surface(setup.display, setup.config, native_window, attributes)
+        }
+        .map_err(egl_error("eglCreateWindowSurface"))?;
+        Ok(Self { setup, surface, api })
+    }
+
+    pub fn api(&self) -> EglApi {
+        self.api
+    }
+
+    /// The address of GL function `name` for this context, or null.
+    pub fn get_proc_address(&self, name: &str) -> *const c_void {
+        match self.setup.egl.get_proc_address(name) {
+            Some(function) => function as *const c_void,
+            None => std::ptr::null(),
+        }
+    }
+
+    pub fn make_current(&self) {
+        let EglSetup { egl, display, context, .. } = &self.setup;
+        let surface = Some(self.surface);
+        if let Err(e) = egl.make_current(*display, surface, surface, Some(*context)) {
+            error!("eglMakeCurrent failed: {}", e);
+        }
+    }
+
+    pub fn swap_buffers(&self) {
+        if let Err(e) = self.setup.egl.swap_buffers(self.setup.display, self.surface) {
+            error!("eglSwapBuffers failed: {}", e);
+        }
+    }
+
+    /// Apply `vsync`, with the context current. Returns the swap interval we set, if any; EGL
+    /// can't tell us what the driver picked by default.
+    pub fn set_swap_interval(&self, vsync: Vsync) -> Option<i32> {
+        let interval = match vsync {
+            Vsync::DriverDefault => return None,
+            Vsync::Off => 0,
+            // EGL has no adaptive vsync.
+            Vsync::On | Vsync::Adaptive => 1,
+        };
+        match self.setup.egl.swap_interval(self.setup.display, interval) {
+            Ok(()) => Some(interval),
+            Err(e) => {
+                warn!("eglSwapInterval failed: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn release_current(&self) {
+        let _ = self.setup.egl.make_current(self.setup.display, None, None, None);
+    }
+}
+
+impl Drop for EglRenderer {
+    fn drop(&mut self) {
+        // The context and display go with the setup.
+        let _ = self.setup.egl.destroy_surface(self.setup.display, self.surface);
+    }
+}
@@ -0,0 +1,298 @@
+// One X connection and one GUI thread for all the threaded windows in the process. A host with
+// dozens of plugin editors open would otherwise have dozens of each.
+//
+// The thread is started by the first window and stops when the last one closes, or when the
+// library is unloaded with windows still open.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::thread;
+use std::time::Duration;
+
+use log::*;
+
+use crate::error::WindowError;
+use crate::gui_state::GuiState;
+use crate::window::WindowOptions;
+use super::proxy::{self, Shared};
+use super::waker::Waker;
+use super::window_context::{self, WindowContext, WindowInfo};
+use super::x_handle::XHandle;
+
+/// How long opening or closing a window (or stopping the thread) waits for the GUI thread, e.g. if
+/// a `GuiState` is stuck in a callback. After that, we give up rather than hang the host.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The running event thread, if any. Only windows hold strong references.
+static EVENT_THREAD: Mutex<Weak<EventThread>> = Mutex::new(Weak::new());
+static REGISTER_UNLOAD_HOOK: Once = Once::new();
+
+enum Command {
+    Open {
+        state: Box<dyn GuiState>,
+        parent_id: u32,
+        size: (u32, u32),
+        options: Box<WindowOptions>,
+        shared: Arc<Shared>,
+        reply: mpsc::Sender<Result<WindowInfo, OpenError>>,
+    },
+    Close {
+        window_id: u32,
+        reply: mpsc::Sender<Option<Box<dyn GuiState>>>,
+    },
+}
+
+/// Why a window couldn't be opened, and its `GuiState` if it could be saved.
+pub type OpenError = (WindowError, Option<Box<dyn GuiState>>);
+
+/// What the window threads and the event thread share.
+struct Inbox {
+    commands: Mutex<Vec<Command>>,
+    stop: AtomicBool,
+    waker: Arc<Waker>,
+}
+
+impl Inbox {
+    fn send(&self, command: Command) {
+        self.commands.lock().unwrap().push(command);
+        self.waker.wake();
+    }
+}
+
+pub struct EventThread {
+    x_handle: Arc<XHandle>,
+    inbox: Arc<Inbox>,
+    // The thread, and a channel that disconnects when it's done. `None` once it's stopped.
+    thread: Mutex<Option<(thread::JoinHandle<()>, mpsc::Receiver<()>)>>,
+}
+
+impl EventThread {
+    /// The process's event thread, started if it isn't running.
+    pub fn get() -> Result<Arc<Self>, WindowError> {
+        let mut current = EVENT_THREAD.lock().unwrap();
+        if let Some(event_thread) = current.upgrade() {
+            return Ok(event_thread);
+        }
+        let event_thread = Arc::new(Self::start()?);
+        *current = Arc::downgrade(&event_thread);
+        REGISTER_UNLOAD_HOOK.call_once(|| unsafe {
+            // atexit() in a shared library registers with that library, so glibc runs this when
+            // the host unloads us, as well as at exit.
+            libc::atexit(stop_at_unload);
+        });
+        Ok(event_thread)
+    }
+
+    fn start() -> Result<Self, WindowError> {
+        let x_handle = Arc::new(XHandle::new()?);
+        let inbox = Arc::new(Inbox {
+            commands: Mutex::new(Vec::new()),
+            stop: AtomicBool::new(false),
+            waker: Arc::new(Waker::new()?),
+        });
+
+        let thread_x_handle = x_handle.clone();
+        let thread_inbox = inbox.clone();
+        let (finished_sender, finished) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("vst2-window events".to_string())
+            .spawn(move || {
+                let _finished_sender = finished_sender;
+                info!("Event thread started");
+                run_event_loop(&thread_x_handle, &thread_inbox);
+                info!("Event thread stopped");
+            })
+            .map_err(|e| WindowError::Os(format!("could not start the event thread: {}", e)))?;
+
+        Ok(Self {
+            x_handle,
+            inbox,
+            thread: Mutex::new(Some((handle, finished))),
+        })
+    }
+
+    pub fn x_handle(&self) -> &Arc<XHandle> {
+        &self.x_handle
+    }
+
+    pub fn waker(&self) -> Arc<Waker> {
+        self.inbox.waker.clone()
+    }
+
+    /// Create a window on the event thread. On failure, the `GuiState` comes back unless the
+    /// thread died or didn't answer in time.
+    pub fn open_window(
+        &self,
+        state: Box<dyn GuiState>,
+        parent_id: u32,
+        size: (u32, u32),
+        options: WindowOptions,
+        shared: Arc<Shared>,
+    ) -> Result<WindowInfo, OpenError> {
+        let (reply, result) = mpsc::channel();
+        self.inbox.send(Command::Open {
+            state,
+            parent_id,
+            size,
+            options: Box::new(options),
+            shared,
+            reply,
+        });
+        match result.recv_timeout(SHUTDOWN_TIMEOUT) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                error!("The event thread didn't open the window within {:?}", SHUTDOWN_TIMEOUT);
+                Err((WindowError::ThreadTimedOut, None))
+            }
+            // If the thread is gone, the sender went with it.
+            Err(RecvTimeoutError::Disconnected) => Err((WindowError::ThreadDied, None)),
+        }
+    }
+
+    /// Destroy a window and hand back its `GuiState`. Gives up after `SHUTDOWN_TIMEOUT`.
+    pub fn close_window(&self, window_id: u32) -> Option<Box<dyn GuiState>> {
+        let (reply, result) = mpsc::channel();
+        self.inbox.send(Command::Close { window_id, reply });
+        match result.recv_timeout(SHUTDOWN_TIMEOUT) {
+            Ok(state) => state,
+            Err(RecvTimeoutError::Timeout) => {
+                error!("The event thread didn't close the window within {:?}", SHUTDOWN_TIMEOUT);
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("The event thread is gone; the GuiState is lost");
+                None
+            }
+        }
+    }
+
+    /// Stop the thread, destroying any windows left. Safe to call more than once.
+    fn stop(&self) {
+        let thread = self.thread.lock().unwrap().take();
+        let (handle, finished) = match thread {
+            Some(thread) => thread,
+            None => return,
+        };
+        self.inbox.stop.store(true, Ordering::Release);
+        self.inbox.waker.wake();
+        match finished.recv_timeout(SHUTDOWN_TIMEOUT) {
+            Err(RecvTimeoutError::Timeout) => {
+                error!("The event thread didn't stop within {:?}; abandoning it", SHUTDOWN_TIMEOUT);
+            }
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                if handle.join().is_err() {
+                    error!("The event thread panicked");
+                }
+            }
+        }
+    }
+}
+
+impl Drop for EventThread {
+    fn drop(&mut self) {
+        // The last window closed.
+        self.stop();
+    }
+}
+
+extern "C" fn stop_at_unload() {
+    // Windows still open at this point are never closed properly, but at least the thread won't
+    // run on in unmapped code.
+    let event_thread = EVENT_THREAD.lock().ok().and_then(|current| current.upgrade());
+    if let Some(event_thread) = event_thread {
+        event_thread.stop();
+    }
+}
+
+fn run_event_loop(x_handle: &Arc<XHandle>, inbox: &Inbox) {
+    let mut windows: Vec<WindowContext> = Vec::new();
+    let mut connection_lost = false;
+    loop {
+        let commands = std::mem::take(&mut *inbox.commands.lock().unwrap());
+        for command in commands {
+            handle_command(x_handle, &mut windows, connection_lost, command);
+        }
+        if inbox.stop.load(Ordering::Acquire) {
+            for context in windows.drain(..) {
+                drop(context.into_state());
+            }
+            x_handle.flush();
+            return;
+        }
+
+        // Always drain XCB's queue before blocking; it may already hold events that were read
+        // in while waiting for a reply, and poll() won't tell us about those.
+        while let Some(ev) = x_handle.poll_for_event() {
+            dispatch_event(&mut windows, &ev);
+        }
+        if !connection_lost && x_handle.has_error() {
+            // Keep serving commands, so the windows can still be closed.
+            error!("Lost the connection to the X server; dropping all windows");
+            connection_lost = true;
+            windows.clear();
+        }
+
+        let mut timeout = None;
+        for context in windows.iter_mut().filter(|context| !context.is_closed()) {
+            context.handle_pending();
+            timeout = proxy::min_timeout(timeout, context.wait_timeout());
+        }
+        x_handle.flush();
+
+        inbox.waker.wait(x_handle.connection_fd(), timeout);
+        // Drain before handling anything, so a wake() that comes in while we're busy isn't lost.
+        inbox.waker.drain();
+    }
+}
+
+fn handle_command(
+    x_handle: &Arc<XHandle>,
+    windows: &mut Vec<WindowContext>,
+    connection_lost: bool,
+    command: Command,
+) {
+    match command {
+        Command::Open { state, parent_id, size, options, shared, reply } => {
+            let mut state = Some(state);
+            let result = if connection_lost {
+                Err(WindowError::NoDisplay)
+            } else {
+                WindowContext::new(x_handle.clone(), shared, &mut state, parent_id, size, &options)
+            };
+            match result {
+                Ok(context) => {
+                    // If open_window() gave up waiting, nobody would ever close this one.
+                    if reply.send(Ok(context.info())).is_ok() {
+                        windows.push(context);
+                    } else {
+                        drop(context.into_state());
+                    }
+                }
+                Err(e) => {
+                    let _ = reply.send(Err((e, state)));
+                }
+            }
+        }
+        Command::Close { window_id, reply } => {
+            let position = windows.iter().position(|context| context.window_id() == window_id);
+            let state = position.map(|index| windows.swap_remove(index).into_state());
+            let _ = reply.send(state);
+        }
+    }
+}
+
+/// Hand an event to the windows it's for. Everything else (keymap and settings changes, the
+/// settings daemon going away) goes to all of them. Windows being closed get nothing.
+fn dispatch_event(windows: &mut [WindowContext], ev: &xcb::GenericEvent) {
+    let window = window_context::event_window(ev);
+    for context in windows.iter_mut().filter(|context| !context.is_closed()) {
+        let wanted = match window {
+            Some(window) => context.watches(window),
+            None => true,
+        };
+        if wanted {
+            context.handle_event(ev);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Frame timing for `GuiState::draw()`, and when the next frame is due under a `RedrawPolicy`.
+
+use std::time::{Duration, Instant};
+
+use crate::gui_state::Frame;
+use crate::window::RedrawPolicy;
+
+pub struct FrameClock {
+    policy: RedrawPolicy,
+    last_frame: Option<Instant>,
+}
+
+impl FrameClock {
+    pub fn new(policy: RedrawPolicy) -> Self {
+        Self {
+            policy,
+            last_frame: None,
+        }
+    }
+
+    /// Start a frame at `now`.
+    pub fn start_frame(&mut self, now: Instant) -> Frame {
+        let delta = match self.last_frame {
+            Some(last_frame) => now.saturating_duration_since(last_frame),
+            None => Duration::from_secs(0),
+        };
+        self.last_frame = Some(now);
+        Frame { time: now, delta }
+    }
+
+    /// When the policy wants the next frame, or `None` if only requests cause redraws. Before
+    /// the first frame, that's `now`.
+    pub fn next_frame(&self, now: Instant) -> Option<Instant> {
+        let interval = match self.policy {
+            RedrawPolicy::OnDemand => return None,
+            RedrawPolicy::Continuous => Duration::from_secs(0),
+            RedrawPolicy::MaxFps(fps) => Duration::from_secs(1) / fps.max(1),
+        };
+        Some(match self.last_frame {
+            Some(last_frame) => last_frame + interval,
+            None => now,
+        })
+    }
+
+    /// Whether the policy wants a frame at `now`.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_frame(now).is_some_and(|next_frame| next_frame <= now)
+    }
+
+    /// How long until the policy wants the next frame, if it does.
+    pub fn time_until_next_frame(&self, now: Instant) -> Option<Duration> {
+        self.next_frame(now).map(|next_frame| next_frame.saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_fps_spaces_frames() {
+        let start = Instant::now();
+        let mut clock = FrameClock::new(RedrawPolicy::MaxFps(50));
+        assert_eq!(clock.start_frame(start).delta, Duration::from_secs(0));
+        assert!(!clock.is_due(start + Duration::from_millis(19)));
+        assert!(clock.is_due(start + Duration::from_millis(20)));
+        assert_eq!(
+            clock.time_until_next_frame(start + Duration::from_millis(5)),
+            Some(Duration::from_millis(15))
+        );
+
+        let frame = clock.start_frame(start + Duration::from_millis(25));
+        assert_eq!(frame.delta, Duration::from_millis(25));
+
+        let on_demand = FrameClock::new(RedrawPolicy::OnDemand);
+        assert!(!on_demand.is_due(start + Duration::from_secs(10)));
+        assert_eq!(on_demand.time_until_next_frame(start), None);
+    }
+}
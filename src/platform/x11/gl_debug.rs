@@ -0,0 +1,168 @@
+// GL debug output (KHR_debug or ARB_debug_output) for debug contexts, forwarded to the log crate.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::sync::Mutex;
+
+use glow::HasContext;
+use log::*;
+
+use crate::gui_state::GlInfo;
+use crate::window::GlDebugBreak;
+
+type GlDebugProc = extern "system" fn(u32, u32, u32, u32, i32, *const c_char, *mut c_void);
+type GlDebugMessageCallbackProc = unsafe extern "system" fn(GlDebugProc, *const c_void);
+
+/// The installed debug callback's state. The GL keeps a pointer to this, so it's boxed, and must
+/// outlive the context (or at least every GL call made with it).
+pub struct DebugOutput {
+    debug_break: GlDebugBreak,
+    // A high severity message that `check()` should panic with. Panicking inside the callback
+    // would unwind through the driver.
+    pending_panic: Mutex<Option<String>>,
+}
+
+impl DebugOutput {
+    /// Install the debug callback on the current context, which `gl` was loaded for and `info`
+    /// describes. `get_proc_address` looks up the context's functions. Returns `None` if the
+    /// context doesn't support debug output.
+    ///
+    /// Unsafe because a context has to be current.
+    pub unsafe fn install(
+        gl: &glow::Context,
+        info: &GlInfo,
+        get_proc_address: impl Fn(&str) -> *const c_void,
+        debug_break: GlDebugBreak,
+    ) -> Option<Box<Self>> {
+        // glow only knows the KHR_debug version, so we load the callback setter ourselves.
+        let names: &[&str] = if info.version >= (4, 3) || info.has_extension("GL_KHR_debug") {
+            &["glDebugMessageCallback", "glDebugMessageCallbackKHR"]
+        } else if info.has_extension("GL_ARB_debug_output") {
+            &["glDebugMessageCallbackARB"]
+        } else {
+            &[]
+        };
+        let set_callback = names.iter().map(|name| get_proc_address(name)).find(|f| !f.is_null());
+        let set_callback = match set_callback {
+            Some(set_callback) => set_callback,
+            None => {
+                info!("The GL context has no debug output (KHR_debug or ARB_debug_output)");
+                return None;
+            }
+        };
+        let set_callback: GlDebugMessageCallbackProc = std::mem::transmute(set_callback);
+
+        let output = Box::new(Self {
+            debug_break,
+            pending_panic: Mutex::new(None),
+        });
+        // Synchronous, so the callback runs on our thread, inside the GL call that caused it.
+        // That's what makes breaking on a message useful.
+        gl.enable(glow::DEBUG_OUTPUT_SYNCHRONOUS);
+        set_callback(callback, &*output as *const Self as *const c_void);
+        // KHR_debug also has an on/off switch. Debug contexts start with it on, but some drivers
+        // need telling; with only ARB_debug_output, this is an INVALID_ENUM we clear right away.
+        gl.enable(glow::DEBUG_OUTPUT);
+        while gl.get_error() != glow::NO_ERROR {}
+        Some(output)
+    }
+
+    /// Panic with the last high severity message since the previous call, if there was one and
+    /// `GlDebugBreak::Panic` is set. Call this with no GL context current.
+    pub fn check(&self) {
+        if let Some(message) = self.pending_panic.lock().unwrap().take() {
+            panic!("{}", message);
+        }
+    }
+
+    fn handle_message(&self, source: u32, kind: u32, id: u32, severity: u32, text: &str) {
+        let level = match severity {
+            glow::DEBUG_SEVERITY_HIGH => Level::Error,
+            glow::DEBUG_SEVERITY_MEDIUM => Level::Warn,
+            glow::DEBUG_SEVERITY_LOW => Level::Info,
+            _ => Level::Debug,
+        };
+        let message = format!(
+            "GL {} from {} (id {}): {}",
+            type_name(kind),
+            source_name(source),
+            id,
+            text.trim_end(),
+        );
+        log!(level, "{}", message);
+
+        if severity == glow::DEBUG_SEVERITY_HIGH {
+            match self.debug_break {
+                GlDebugBreak::Off => {}
+                GlDebugBreak::Panic => *self.pending_panic.lock().unwrap() = Some(message),
+                GlDebugBreak::Trap => unsafe {
+                    libc::raise(libc::SIGTRAP);
+                },
+            }
+        }
+    }
+}
+
+extern "system" fn callback(
+    source: u32,
+    kind: u32,
+    id: u32,
+    severity: u32,
+    length: i32,
+    message: *const c_char,
+    user_param: *mut c_void,
+) {
+    let output = unsafe { &*(user_param as *const DebugOutput) };
+    let text = unsafe {
+        if length >= 0 {
+            let bytes = std::slice::from_raw_parts(message as *const u8, length as usize);
+            String::from_utf8_lossy(bytes)
+        } else {
+            CStr::from_ptr(message).to_string_lossy()
+        }
+    };
+    output.handle_message(source, kind, id, severity, &text);
+}
+
+fn source_name(source: u32) -> &'static str {
+    match source {
+        glow::DEBUG_SOURCE_API => "API",
+        glow::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        glow::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        glow::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        glow::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other source",
+    }
+}
+
+fn type_name(kind: u32) -> &'static str {
+    match kind {
+        glow::DEBUG_TYPE_ERROR => "error",
+        glow::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+        glow::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        glow::DEBUG_TYPE_PORTABILITY => "portability issue",
+        glow::DEBUG_TYPE_PERFORMANCE => "performance issue",
+        glow::DEBUG_TYPE_MARKER => "marker",
+        _ => "message",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "GL error from API (id 7): bad enum")]
+    fn high_severity_panics_on_check() {
+        let output = DebugOutput {
+            debug_break: GlDebugBreak::Panic,
+            pending_panic: Mutex::new(None),
+        };
+        let api = glow::DEBUG_SOURCE_API;
+        let (low, high) = (glow::DEBUG_SEVERITY_LOW, glow::DEBUG_SEVERITY_HIGH);
+        output.handle_message(api, glow::DEBUG_TYPE_PERFORMANCE, 1, low, "slow");
+        output.check();
+        output.handle_message(api, glow::DEBUG_TYPE_ERROR, 7, high, "bad enum\n");
+        output.check();
+    }
+}
@@ -1,13 +1,18 @@
 // TODO: This file is pretty dang messy. Clean up.
 
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_int, c_void};
+use std::os::raw::{c_int, c_uint, c_void};
 use std::ptr::null_mut;
 use std::sync::Arc;
 
-use x11::{glx, xlib};
+use glow::HasContext;
+use x11::glx::{self, arb, ext};
+use x11::xlib;
 use log::*;
 
+use crate::error::WindowError;
+use crate::gui_state::GlInfo;
+use crate::window::{GlConfig, GlProfile, Vsync};
 use super::x_handle;
 
 type GlXCreateContextAttribsARBProc = unsafe extern "C" fn(
@@ -18,32 +23,47 @@ type GlXCreateContextAttribsARBProc = unsafe extern "C" fn(
     attribs: *const c_int,
 ) -> glx::GLXContext;
 
-pub unsafe fn check_gl_error() {
-    let err = gl::GetError();
-    if err != gl::NO_ERROR {
+type GlXSwapIntervalEXTProc =
+    unsafe extern "C" fn(dpy: *mut xlib::Display, drawable: glx::GLXDrawable, interval: c_int);
+type GlXSwapIntervalMESAProc = unsafe extern "C" fn(interval: c_uint) -> c_int;
+type GlXGetSwapIntervalMESAProc = unsafe extern "C" fn() -> c_int;
+type GlXSwapIntervalSGIProc = unsafe extern "C" fn(interval: c_int) -> c_int;
+
+pub unsafe fn check_gl_error(gl: &glow::Context) {
+    let err = gl.get_error();
+    if err != glow::NO_ERROR {
         info!("got gl error {}", err);
     }
 }
 
-const GLX_CONTEXT_MAJOR_VERSION_ARB: u32 = 0x2091;
-const GLX_CONTEXT_MINOR_VERSION_ARB: u32 = 0x2092;
+// From GLX_EXT_swap_control_tear.
+const GLX_LATE_SWAPS_TEAR_EXT: c_int = 0x20f3;
+
+// From GLX_ARB_framebuffer_sRGB (and the EXT version, which has the same value).
+const GLX_FRAMEBUFFER_SRGB_CAPABLE_ARB: c_int = 0x20b2;
 
-pub fn glx_dec_version(dpy: *mut xlib::Display) -> i32 {
+pub fn glx_dec_version(dpy: *mut xlib::Display) -> Result<i32, WindowError> {
     let mut maj: c_int = 0;
     let mut min: c_int = 0;
     unsafe {
         if glx::glXQueryVersion(dpy, &mut maj as *mut c_int, &mut min as *mut c_int) == 0 {
-            panic!("cannot get glx version");
+            return Err(WindowError::NoGlx);
         }
     }
-    (maj * 10 + min) as i32
+    Ok((maj * 10 + min) as i32)
 }
 
 pub fn get_glxfbconfig(
     dpy: *mut xlib::Display,
     screen_num: i32,
     visual_attribs: &[i32],
-) -> glx::GLXFBConfig {
+) -> Result<glx::GLXFBConfig, WindowError> {
+    // glXChooseFBConfig() needs GLX 1.3.
+    let glx_version = glx_dec_version(dpy)?;
+    if glx_version < 13 {
+        return Err(WindowError::GlxVersionTooOld(glx_version));
+    }
+
     unsafe {
         let mut fbcount: c_int = 0;
         let fbcs = glx::glXChooseFBConfig(
@@ -53,92 +73,265 @@ pub fn get_glxfbconfig(
             &mut fbcount as *mut c_int,
         );
 
-        if fbcount == 0 {
-            panic!("could not find compatible fb config");
+        if fbcs.is_null() || fbcount == 0 {
+            return Err(WindowError::NoMatchingFbConfig);
         }
 
         // Pick the first from the list
         let fbc = *fbcs;
         xlib::XFree(fbcs as *mut c_void);
-        fbc
+        Ok(fbc)
     }
 }
 
-static mut GL_CONTEXT_ERROR_OCCURRED: bool = false;
-unsafe extern "C" fn gl_context_error_handler(
-    _dpy: *mut xlib::Display,
-    _ev: *mut xlib::XErrorEvent,
-) -> i32 {
-    GL_CONTEXT_ERROR_OCCURRED = true;
-    0
+/// The extensions the screen's GLX supports, separated by spaces. Empty if there's no GLX.
+pub fn glx_extensions(x_handle: &x_handle::XHandle) -> &'static str {
+    unsafe {
+        let extensions =
+            glx::glXQueryExtensionsString(x_handle.raw_display(), x_handle.screen_num());
+        if extensions.is_null() {
+            return "";
+        }
+        CStr::from_ptr(extensions).to_str().unwrap_or("")
+    }
 }
 
-pub fn create_gl_context(x_handle: Arc<x_handle::XHandle>, glx_frame_buffer_config: *mut glx::__GLXFBConfigRec) -> *mut x11::glx::__GLXcontextRec {
-    // Load GL extensions
-    let glx_exts = unsafe {
-        CStr::from_ptr(
-            glx::glXQueryExtensionsString(x_handle.raw_display(), x_handle.screen_num()))
-            .to_str().unwrap()
-    };
+/// The `glXChooseFBConfig()` attributes for `config`.
+pub fn fbconfig_attributes(config: &GlConfig) -> Vec<c_int> {
+    #[rustfmt::skip]
+    let mut attributes = vec![
+        glx::GLX_X_RENDERABLE, 1,
+        glx::GLX_DRAWABLE_TYPE, glx::GLX_WINDOW_BIT,
+        glx::GLX_RENDER_TYPE, glx::GLX_RGBA_BIT,
+        glx::GLX_X_VISUAL_TYPE, glx::GLX_TRUE_COLOR,
+        glx::GLX_RED_SIZE, 8,
+        glx::GLX_GREEN_SIZE, 8,
+        glx::GLX_BLUE_SIZE, 8,
+        glx::GLX_ALPHA_SIZE, 8,
+        glx::GLX_DEPTH_SIZE, config.depth_bits as c_int,
+        glx::GLX_STENCIL_SIZE, config.stencil_bits as c_int,
+        glx::GLX_DOUBLEBUFFER, 1,
+    ];
+    if config.samples > 0 {
+        attributes.extend_from_slice(&[
+            glx::GLX_SAMPLE_BUFFERS, 1,
+            glx::GLX_SAMPLES, config.samples as c_int,
+        ]);
+    }
+    if config.srgb {
+        attributes.extend_from_slice(&[GLX_FRAMEBUFFER_SRGB_CAPABLE_ARB, 1]);
+    }
+    attributes.push(0);
+    attributes
+}
+
+/// The `glXCreateContextAttribsARB()` attributes for a context of `version`.
+fn context_attributes(config: &GlConfig, version: (u8, u8)) -> Vec<c_int> {
+    #[rustfmt::skip]
+    let mut attributes = vec![
+        arb::GLX_CONTEXT_MAJOR_VERSION_ARB, version.0 as c_int,
+        arb::GLX_CONTEXT_MINOR_VERSION_ARB, version.1 as c_int,
+    ];
+    // Profiles only exist since 3.2; asking for one before that is an error.
+    if version >= (3, 2) {
+        let profile = match config.profile {
+            GlProfile::Core => arb::GLX_CONTEXT_CORE_PROFILE_BIT_ARB,
+            GlProfile::Compatibility => arb::GLX_CONTEXT_COMPATIBILITY_PROFILE_BIT_ARB,
+        };
+        attributes.extend_from_slice(&[arb::GLX_CONTEXT_PROFILE_MASK_ARB, profile]);
+    }
+    let mut flags = 0;
+    if config.debug {
+        flags |= arb::GLX_CONTEXT_DEBUG_BIT_ARB;
+    }
+    if config.forward_compatible && version >= (3, 0) {
+        flags |= arb::GLX_CONTEXT_FORWARD_COMPATIBLE_BIT_ARB;
+    }
+    if flags != 0 {
+        attributes.extend_from_slice(&[arb::GLX_CONTEXT_FLAGS_ARB, flags]);
+    }
+    attributes.push(0);
+    attributes
+}
+
+/// Create a context for `config.version`, or else the first of `config.fallback_versions` that
+/// works.
+pub fn create_gl_context(
+    x_handle: Arc<x_handle::XHandle>,
+    glx_frame_buffer_config: *mut glx::__GLXFBConfigRec,
+    config: &GlConfig,
+) -> Result<*mut x11::glx::__GLXcontextRec, WindowError> {
+    let glx_exts = glx_extensions(&x_handle);
 
     // We need at least the GLX_ARB_create_context extension to continue.
-    if !check_glx_extension(&glx_exts, "GLX_ARB_create_context") {
-        panic!("could not find GLX extension GLX_ARB_create_context");
+    if !check_glx_extension(glx_exts, "GLX_ARB_create_context") {
+        return Err(WindowError::MissingGlxExtension("GLX_ARB_create_context"));
     }
+    let has_profiles = check_glx_extension(glx_exts, "GLX_ARB_create_context_profile");
 
     // We have to load the "glXCreateContextAttribsARB" GL function differently, for some reason. (???)
     let glx_create_context_attribs: GlXCreateContextAttribsARBProc = unsafe {
-        std::mem::transmute(load_gl_func("glXCreateContextAttribsARB"))
+        std::mem::transmute(load_gl_func("glXCreateContextAttribsARB")?)
     };
 
-    // Now we can load all of the other GL functions.
-    unsafe {
-        gl::load_with(|n| load_gl_func(&n));
-    }
+    let mut result = Err(WindowError::ContextCreationFailed);
+    for version in config.versions() {
+        if version >= (3, 2) && !has_profiles {
+            warn!("Skipping OpenGL {}.{}: no GLX_ARB_create_context_profile", version.0, version.1);
+            result = Err(WindowError::MissingGlxExtension("GLX_ARB_create_context_profile"));
+            continue;
+        }
+        // glXGetProcAddress() doesn't know about the context, so this only weeds out libGLs
+        // that have never heard of OpenGL 3.
+        if version >= (3, 0) && unsafe { load_gl_func("glGenVertexArrays") }.is_err() {
+            warn!("Skipping OpenGL {}.{}: no glGenVertexArrays", version.0, version.1);
+            result = Err(WindowError::MissingGlFunction("glGenVertexArrays".to_string()));
+            continue;
+        }
 
-    // We need to ensure that this function is loaded, or else we don't have OpenGL 3 support.
-    if !gl::GenVertexArrays::is_loaded() {
-        panic!("no GL3 support available!");
+        let attributes = context_attributes(config, version);
+        result = try_create_context(
+            &x_handle,
+            glx_create_context_attribs,
+            glx_frame_buffer_config,
+            &attributes,
+        );
+        match &result {
+            Ok(_) => {
+                info!("Created an OpenGL {}.{} context", version.0, version.1);
+                break;
+            }
+            // Every other version would be indirect too.
+            Err(WindowError::IndirectContext) => break,
+            Err(e) => {
+                warn!("Could not create an OpenGL {}.{} context: {}", version.0, version.1, e)
+            }
+        }
     }
+    result
+}
+
+fn try_create_context(
+    x_handle: &x_handle::XHandle,
+    glx_create_context_attribs: GlXCreateContextAttribsARBProc,
+    glx_frame_buffer_config: *mut glx::__GLXFBConfigRec,
+    context_attributes: &[c_int],
+) -> Result<*mut x11::glx::__GLXcontextRec, WindowError> {
+    let ctx = x_handle.trap_errors("glXCreateContextAttribsARB", || unsafe {
+        glx_create_context_attribs(x_handle.raw_display(), glx_frame_buffer_config, null_mut(),
+                                   xlib::True, context_attributes.as_ptr())
+    })?;
 
-    // Install a context error handler
     unsafe {
-        GL_CONTEXT_ERROR_OCCURRED = false;
+        if ctx.is_null() {
+            return Err(WindowError::ContextCreationFailed);
+        }
+
+        if glx::glXIsDirect(x_handle.raw_display(), ctx) == 0 {
+            glx::glXDestroyContext(x_handle.raw_display(), ctx);
+            return Err(WindowError::IndirectContext);
+        }
     }
-    let old_handler = unsafe {
-        xlib::XSetErrorHandler(Some(gl_context_error_handler))
-    };
 
-    // Create the context attributes
-    let context_attributes: [c_int; 5] = [
-        GLX_CONTEXT_MAJOR_VERSION_ARB as c_int, 3,
-        GLX_CONTEXT_MINOR_VERSION_ARB as c_int, 0,
-        0
-    ];
+    Ok(ctx)
+}
 
-    // And finally, create the context itself
-    let ctx = unsafe {
-        glx_create_context_attribs(x_handle.raw_display(), glx_frame_buffer_config, null_mut(),
-                                   xlib::True, &context_attributes[0] as *const c_int)
-    };
+/// Ask the current context, which `gl` was loaded for, what it is.
+pub unsafe fn query_gl_info(gl: &glow::Context) -> GlInfo {
+    let version_string = gl.get_parameter_string(glow::VERSION);
+    let version = parse_gl_version(&version_string);
+    let mut extensions: Vec<String> = gl.supported_extensions().iter().cloned().collect();
+    extensions.sort();
+    while gl.get_error() != glow::NO_ERROR {}
 
-    x_handle.flush();
-    unsafe {
-        xlib::XSync(x_handle.raw_display(), xlib::False);
-        xlib::XSetErrorHandler(std::mem::transmute(old_handler));
+    GlInfo {
+        version,
+        version_string,
+        renderer: gl.get_parameter_string(glow::RENDERER),
+        vendor: gl.get_parameter_string(glow::VENDOR),
+        extensions,
     }
+}
 
-    unsafe {
-        if ctx.is_null() || GL_CONTEXT_ERROR_OCCURRED {
-            panic!("error when creating gl-3.0 context");
+/// The version number at the start of a `GL_VERSION` string: "4.6 (Core Profile) Mesa 23.2.1"
+/// for desktop GL, "OpenGL ES 3.2 Mesa 23.2.1" for ES.
+fn parse_gl_version(version: &str) -> (u32, u32) {
+    let number = version
+        .split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+        .unwrap_or("");
+    let mut parts = number.split('.').map(|part| part.parse().unwrap_or(0));
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
+
+/// Apply `vsync` to `window`, with its context current. Returns the swap interval in effect
+/// afterwards, if we can find out.
+pub unsafe fn set_swap_interval(
+    x_handle: &x_handle::XHandle,
+    window: xlib::XID,
+    vsync: Vsync,
+) -> Option<i32> {
+    let glx_exts = glx_extensions(x_handle);
+    let can_tear = check_glx_extension(glx_exts, "GLX_EXT_swap_control_tear");
+    let interval = match vsync {
+        Vsync::DriverDefault => None,
+        Vsync::Off => Some(0),
+        Vsync::On => Some(1),
+        Vsync::Adaptive if can_tear => Some(-1),
+        Vsync::Adaptive => {
+            info!("No GLX_EXT_swap_control_tear; using plain vsync instead of adaptive");
+            Some(1)
         }
+    };
+    let display = x_handle.raw_display();
 
-        if glx::glXIsDirect(x_handle.raw_display(), ctx) == 0 {
-            panic!("obtained indirect rendering context")
+    if check_glx_extension(glx_exts, "GLX_EXT_swap_control") {
+        if let Some(interval) = interval {
+            let swap_interval: GlXSwapIntervalEXTProc =
+                std::mem::transmute(load_gl_func("glXSwapIntervalEXT").ok()?);
+            swap_interval(display, window, interval);
+        }
+        // The interval reads back as its absolute value; adaptive vsync is a separate attribute.
+        let mut value = 0;
+        glx::glXQueryDrawable(display, window, ext::GLX_SWAP_INTERVAL_EXT, &mut value);
+        let mut late_swaps_tear = 0;
+        if can_tear {
+            glx::glXQueryDrawable(display, window, GLX_LATE_SWAPS_TEAR_EXT, &mut late_swaps_tear);
+        }
+        Some(if late_swaps_tear != 0 { -(value as i32) } else { value as i32 })
+    } else if check_glx_extension(glx_exts, "GLX_MESA_swap_control") {
+        if let Some(interval) = interval {
+            let swap_interval: GlXSwapIntervalMESAProc =
+                std::mem::transmute(load_gl_func("glXSwapIntervalMESA").ok()?);
+            swap_interval(interval as c_uint);
+        }
+        let get_swap_interval: GlXGetSwapIntervalMESAProc =
+            std::mem::transmute(load_gl_func("glXGetSwapIntervalMESA").ok()?);
+        Some(get_swap_interval())
+    } else if check_glx_extension(glx_exts, "GLX_SGI_swap_control") {
+        // This one can't turn vsync off, and has no way to ask for the current interval.
+        match interval {
+            Some(interval) if interval > 0 => {
+                let swap_interval: GlXSwapIntervalSGIProc =
+                    std::mem::transmute(load_gl_func("glXSwapIntervalSGI").ok()?);
+                if swap_interval(interval) == 0 {
+                    Some(interval)
+                } else {
+                    None
+                }
+            }
+            Some(_) => {
+                warn!("Only GLX_SGI_swap_control is available, which can't turn vsync off");
+                None
+            }
+            None => None,
         }
+    } else {
+        if interval.is_some() {
+            warn!("No GLX swap control extension; can't change vsync");
+        }
+        None
     }
-
-    ctx
 }
 
 fn check_glx_extension(glx_exts: &str, ext_name: &str) -> bool {
@@ -150,11 +343,74 @@ fn check_glx_extension(glx_exts: &str, ext_name: &str) -> bool {
     false
 }
 
-unsafe fn load_gl_func(name: &str) -> *mut c_void {
-    let cname = CString::new(name).unwrap();
+/// The address of GL or GLX function `name`, or null. Under GLX, it's the same for every context.
+pub fn get_proc_address(name: &str) -> *const c_void {
+    unsafe { load_gl_func(name).unwrap_or(null_mut()) }
+}
+
+unsafe fn load_gl_func(name: &str) -> Result<*mut c_void, WindowError> {
+    let cname = CString::new(name).map_err(|_| WindowError::MissingGlFunction(name.to_string()))?;
     let ptr: *mut c_void = std::mem::transmute(glx::glXGetProcAddress(cname.as_ptr() as *const u8));
     if ptr.is_null() {
-        panic!("could not load {}", name);
+        return Err(WindowError::MissingGlFunction(name.to_string()));
+    }
+    Ok(ptr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_attributes_per_version() {
+        let config = GlConfig {
+            profile: GlProfile::Core,
+            debug: true,
+            forward_compatible: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            context_attributes(&config, (3, 3)),
+            vec![
+                arb::GLX_CONTEXT_MAJOR_VERSION_ARB, 3,
+                arb::GLX_CONTEXT_MINOR_VERSION_ARB, 3,
+                arb::GLX_CONTEXT_PROFILE_MASK_ARB, arb::GLX_CONTEXT_CORE_PROFILE_BIT_ARB,
+                arb::GLX_CONTEXT_FLAGS_ARB, 3,
+                0,
+            ]
+        );
+        // No profiles before 3.2, and no forward compatible contexts before 3.0.
+        assert_eq!(
+            context_attributes(&config, (2, 1)),
+            vec![
+                arb::GLX_CONTEXT_MAJOR_VERSION_ARB, 2,
+                arb::GLX_CONTEXT_MINOR_VERSION_ARB, 1,
+                arb::GLX_CONTEXT_FLAGS_ARB, arb::GLX_CONTEXT_DEBUG_BIT_ARB,
+                0,
+            ]
+        );
+    }
+
+    #[test]
+    fn gl_version_strings() {
+        assert_eq!(parse_gl_version("4.6 (Core Profile) Mesa 23.2.1"), (4, 6));
+        assert_eq!(parse_gl_version("3.0.0 NVIDIA 535.54"), (3, 0));
+        assert_eq!(parse_gl_version("OpenGL ES 3.2 Mesa 23.2.1"), (3, 2));
+        assert_eq!(parse_gl_version(""), (0, 0));
+    }
+
+    #[test]
+    fn fbconfig_attributes_for_msaa_and_srgb() {
+        let default = fbconfig_attributes(&GlConfig::default());
+        assert!(!default.contains(&glx::GLX_SAMPLES));
+        assert!(!default.contains(&GLX_FRAMEBUFFER_SRGB_CAPABLE_ARB));
+
+        let config = GlConfig { samples: 4, srgb: true, depth_bits: 16, ..Default::default() };
+        let attributes = fbconfig_attributes(&config);
+        let value = |key| attributes.chunks(2).find(|pair| pair[0] == key).map(|pair| pair[1]);
+        assert_eq!(value(glx::GLX_SAMPLES), Some(4));
+        assert_eq!(value(GLX_FRAMEBUFFER_SRGB_CAPABLE_ARB), Some(1));
+        assert_eq!(value(glx::GLX_DEPTH_SIZE), Some(16));
+        assert_eq!(attributes.last(), Some(&0));
     }
-    ptr
 }
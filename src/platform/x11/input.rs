@@ -0,0 +1,66 @@
+// Translation from X11 input events to our own types.
+
+use crate::gui_state::{Modifiers, MouseEvent};
+
+/// Build `Modifiers` from the `state` field of an X input event.
+pub fn modifiers_from_state(state: u16) -> Modifiers {
+    let state = state as u32;
+    Modifiers {
+        shift: state & xcb::MOD_MASK_SHIFT != 0,
+        ctrl: state & xcb::MOD_MASK_CONTROL != 0,
+        alt: state & xcb::MOD_MASK_1 != 0,
+        super_key: state & xcb::MOD_MASK_4 != 0,
+    }
+}
+
+/// Turn an X button number (the `detail` field) into a `MouseEvent`.
+///
+/// The scroll wheel "buttons" (4-7) send a press and a release for every notch, so we only report
+/// the press.
+pub fn mouse_button_event(button: u8, pressed: bool) -> Option<MouseEvent> {
+    if pressed {
+        match button {
+            1 => Some(MouseEvent::LeftMouseButtonDown),
+            2 => Some(MouseEvent::MiddleMouseButtonDown),
+            3 => Some(MouseEvent::RightMouseButtonDown),
+            4 => Some(MouseEvent::ScrollUp),
+            5 => Some(MouseEvent::ScrollDown),
+            6 => Some(MouseEvent::ScrollLeft),
+            7 => Some(MouseEvent::ScrollRight),
+            8 => Some(MouseEvent::BackMouseButtonDown),
+            9 => Some(MouseEvent::ForwardMouseButtonDown),
+            _ => None,
+        }
+    } else {
+        match button {
+            1 => Some(MouseEvent::LeftMouseButtonUp),
+            2 => Some(MouseEvent::MiddleMouseButtonUp),
+            3 => Some(MouseEvent::RightMouseButtonUp),
+            8 => Some(MouseEvent::BackMouseButtonUp),
+            9 => Some(MouseEvent::ForwardMouseButtonUp),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modifiers_from_x_state() {
+        let state = (xcb::MOD_MASK_SHIFT | xcb::MOD_MASK_4 | xcb::MOD_MASK_LOCK) as u16;
+        assert_eq!(
+            modifiers_from_state(state),
+            Modifiers { shift: true, ctrl: false, alt: false, super_key: true }
+        );
+        assert_eq!(modifiers_from_state(0), Modifiers::default());
+    }
+
+    #[test]
+    fn scroll_release_is_ignored() {
+        assert!(mouse_button_event(4, true).is_some());
+        assert!(mouse_button_event(4, false).is_none());
+        assert!(matches!(mouse_button_event(1, false), Some(MouseEvent::LeftMouseButtonUp)));
+    }
+}
@@ -0,0 +1,135 @@
+// Keyboard handling: turns raw X keycodes into keysyms and text using xkbcommon, which reads the
+// keymap (layout, variants, etc.) from the server over our existing XCB connection.
+
+use std::os::raw::c_int;
+
+use as_raw_xcb_connection::ValidConnection;
+use log::*;
+use x11::xlib;
+use xkbcommon::xkb;
+
+use crate::gui_state::KeyEvent;
+use super::input;
+use super::x_handle::XHandle;
+
+pub struct Keyboard {
+    context: xkb::Context,
+    device_id: i32,
+    keymap: xkb::Keymap,
+    state: xkb::State,
+    // Which keycodes are currently held down, so we can flag auto-repeated presses.
+    pressed: [bool; 256],
+}
+
+impl Keyboard {
+    /// Returns `None` if the server doesn't support the XKB extension; we just won't get any
+    /// keyboard events in that case.
+    pub fn new(x_handle: &XHandle) -> Option<Self> {
+        let connection = raw_connection(x_handle);
+        let (mut major, mut minor, mut base_event, mut base_error) = (0, 0, 0, 0);
+        if !xkb::x11::setup_xkb_extension(
+            &connection,
+            xkb::x11::MIN_MAJOR_XKB_VERSION,
+            xkb::x11::MIN_MINOR_XKB_VERSION,
+            xkb::x11::SetupXkbExtensionFlags::NoFlags,
+            &mut major,
+            &mut minor,
+            &mut base_event,
+            &mut base_error,
+        ) {
+            warn!("XKB extension not available; keyboard input disabled");
+            return None;
+        }
+
+        let device_id = xkb::x11::get_core_keyboard_device_id(&connection);
+        if device_id < 0 {
+            warn!("Could not get the core keyboard device; keyboard input disabled");
+            return None;
+        }
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::x11::keymap_new_from_device(
+            &context,
+            &connection,
+            device_id,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+        if keymap.get_raw_ptr().is_null() {
+            warn!("Could not load the keymap; keyboard input disabled");
+            return None;
+        }
+        let state = xkb::x11::state_new_from_device(&keymap, &connection, device_id);
+
+        // Without this, X reports a held key as a stream of release/press pairs. With it, we only
+        // get repeated presses, which we can tell apart from real ones.
+        let mut supported: c_int = 0;
+        unsafe {
+            xlib::XkbSetDetectableAutoRepeat(x_handle.raw_display(), xlib::True, &mut supported);
+        }
+        if supported == 0 {
+            warn!("Detectable auto-repeat not supported; repeated keys will look like new presses");
+        }
+
+        Some(Self {
+            context,
+            device_id,
+            keymap,
+            state,
+            pressed: [false; 256],
+        })
+    }
+
+    /// Re-read the keymap from the server (e.g. after the user switched layouts).
+    pub fn reload_keymap(&mut self, x_handle: &XHandle) {
+        let connection = raw_connection(x_handle);
+        let keymap = xkb::x11::keymap_new_from_device(
+            &self.context,
+            &connection,
+            self.device_id,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+        if keymap.get_raw_ptr().is_null() {
+            warn!("Could not reload the keymap; keeping the old one");
+            return;
+        }
+        self.state = xkb::x11::state_new_from_device(&keymap, &connection, self.device_id);
+        self.keymap = keymap;
+    }
+
+    /// Forget which keys are held down, e.g. when we lose focus and won't see their releases.
+    pub fn reset(&mut self) {
+        self.pressed = [false; 256];
+    }
+
+    /// Translate a KeyPress/KeyRelease event. `x_state` is the event's `state` field.
+    pub fn key_event(&mut self, keycode: u8, x_state: u16, pressed: bool, time: u32) -> KeyEvent {
+        // The core event's state has the modifiers in the low byte and the layout group in bits
+        // 13-14; that's enough to keep xkbcommon in sync without listening for XKB events.
+        let mods = (x_state & 0xff) as u32;
+        let group = ((x_state >> 13) & 0x3) as u32;
+        self.state.update_mask(mods, 0, 0, 0, 0, group);
+
+        let repeat = pressed && self.pressed[keycode as usize];
+        self.pressed[keycode as usize] = pressed;
+
+        let xkb_keycode = xkb::Keycode::new(keycode as u32);
+        let keysym = self.state.key_get_one_sym(xkb_keycode).raw();
+        let text = Some(self.state.key_get_utf8(xkb_keycode))
+            .filter(|text| !text.is_empty() && !text.chars().all(char::is_control));
+
+        KeyEvent {
+            pressed,
+            keycode: keycode as u32,
+            keysym,
+            text,
+            modifiers: input::modifiers_from_state(x_state),
+            repeat,
+            time,
+        }
+    }
+}
+
+fn raw_connection(x_handle: &XHandle) -> ValidConnection {
+    // The XHandle owns the connection and outlives every Keyboard that uses it.
+    unsafe { ValidConnection::new(x_handle.conn_ref().get_raw_conn() as *mut _) }
+}
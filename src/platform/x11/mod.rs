@@ -1,330 +1,342 @@
 // TODO: when you drop() the window, it shows a completely black screen for a split second.
 // Not sure what's causing it, but I don't feel like figuring it out right now.
 
-use std::ffi::{CStr, CString};
-use std::os::raw::{c_int, c_void};
+use std::os::raw::c_void;
+use std::os::unix::io::RawFd;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::ptr::null_mut;
+use std::time::Duration;
 
-use x11::{xlib, glx};
 use log::*;
 
-use crate::window::WindowImpl;
-use crate::gui_state::{GuiState, MouseEvent};
+use crate::error::WindowError;
+use crate::window::{Backend, EventLoopMode, RawWindow, WindowImpl, WindowOptions};
+use crate::gui_state::{GuiState, KeyEvent};
 
+mod clock;
+#[cfg(feature = "egl")]
+mod egl;
+mod event_thread;
+mod frame_clock;
+mod gl_debug;
 mod gl_utils;
-mod thread_gate;
+mod input;
+mod keyboard;
+mod proxy;
+mod scale;
+mod software;
+mod state_guard;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+mod timers;
+mod waker;
+mod window_context;
+mod window_core;
 mod x_handle;
 
+use self::event_thread::EventThread;
+pub use self::proxy::PlatformProxy;
+#[cfg(feature = "testing")]
+pub use self::testing::{DrawCall, MockWindow};
+use self::proxy::Shared;
+use self::waker::Waker;
+use self::window_context::{WindowContext, WindowInfo};
+
+enum Mode {
+    /// The window's events are handled on the event thread all threaded windows share. Holding
+    /// on to it keeps it running.
+    Threaded(Option<Arc<EventThread>>),
+    /// The host handles the window's events on its own thread through `pump_events()`.
+    HostDriven(Option<Box<WindowContext>>),
+}
+
 pub struct PlatformWindow {
-    t: Option<thread::JoinHandle<()>>,
+    mode: Mode,
     x_handle: Arc<x_handle::XHandle>,
-    window_id_mutex: Arc<Mutex<u32>>,          // TODO: atomic?
-    protocols_atom_mutex: Arc<Mutex<u32>>,     // TODO: atomic?
-    delete_window_atom_mutex: Arc<Mutex<u32>>, // TODO: atomic?
+    shared: Arc<Shared>,
+    info: WindowInfo,
+    logical_units: bool,
+    size_mutex: Mutex<(u32, u32)>,
 }
 
-impl WindowImpl for PlatformWindow {
-    fn new(mut state: Box<dyn GuiState>, parent: *mut c_void, size: (u32, u32)) -> Self {
+impl PlatformWindow {
+    /// Like `WindowImpl::new()`, but the `GuiState` is only taken out of `state` when the window
+    /// was created, or when it can't be handed back (the event thread died or hung).
+    pub fn open(
+        state: &mut Option<Box<dyn GuiState>>,
+        parent: *mut c_void,
+        size: (u32, u32),
+        options: WindowOptions,
+    ) -> Result<Self, WindowError> {
         info!("Window::new()");
-        let mut parent_id = parent as u32;
-        let (spawner, spawned) = thread_gate::create_thread_gate();
-
-        // Create an XHandle to handle the XCB connection for us
-        let x_handle = Arc::new(x_handle::XHandle::new());
-        let thread_x_handle = x_handle.clone();
-
-        // We need to get the window_id, protocols_atom, and delete_window_atom values out of the
-        // spawned thread so that we can use them in our drop() function.
-        let window_id_mutex = Arc::new(Mutex::new(0));
-        let thread_window_id_mutex = window_id_mutex.clone();
-        let protocols_atom_mutex = Arc::new(Mutex::new(0));
-        let thread_protocols_atom_mutex = protocols_atom_mutex.clone();
-        let delete_window_atom_mutex = Arc::new(Mutex::new(0));
-        let thread_delete_window_atom_mutex = delete_window_atom_mutex.clone();
-
-        let t = thread::spawn(move || {
-            // Create visual info for the window
-            #[rustfmt::skip]
-            let visual_info_options = &[
-                glx::GLX_X_RENDERABLE, 1,
-                glx::GLX_DRAWABLE_TYPE, glx::GLX_WINDOW_BIT,
-                glx::GLX_RENDER_TYPE, glx::GLX_RGBA_BIT,
-                glx::GLX_X_VISUAL_TYPE, glx::GLX_TRUE_COLOR,
-                glx::GLX_RED_SIZE, 8,
-                glx::GLX_GREEN_SIZE, 8,
-                glx::GLX_BLUE_SIZE, 8,
-                glx::GLX_ALPHA_SIZE, 8,
-                glx::GLX_DEPTH_SIZE, 24,
-                glx::GLX_STENCIL_SIZE, 8,
-                glx::GLX_DOUBLEBUFFER, 1,
-                0
-            ];
-            let glx_frame_buffer_config = gl_utils::get_glxfbconfig(
-                thread_x_handle.raw_display(),
-                thread_x_handle.screen_num(),
-                visual_info_options,
-            );
-            let visual_info = unsafe {
-                glx::glXGetVisualFromFBConfig(
-                    thread_x_handle.raw_display(),
-                    glx_frame_buffer_config,
-                )
-            };
-            let visual_info_id = unsafe { (*visual_info).visualid as u32 };
-            let visual_info_screen = unsafe { (*visual_info).screen as usize };
-            let visual_info_depth = unsafe { (*visual_info).depth as u8 };
-
-            // Get the screen struct from the visual info for creating the colormap and window
-            let screen = thread_x_handle.screen(visual_info_screen);
-            if parent_id == 0 {
-                parent_id = screen.root();
+        let parent_id = parent as u32;
+
+        match options.event_loop {
+            EventLoopMode::Threaded => {
+                let event_thread = EventThread::get()?;
+                let x_handle = event_thread.x_handle().clone();
+                let shared = Arc::new(Shared::new(
+                    event_thread.waker(),
+                    options.parameters.clone(),
+                    options.host_resize.clone(),
+                ));
+                let logical_units = options.logical_units;
+                let boxed_state = state.take().expect("PlatformWindow::open() needs a GuiState");
+                let info = event_thread
+                    .open_window(boxed_state, parent_id, size, options, shared.clone())
+                    .map_err(|(e, returned_state)| {
+                        *state = returned_state;
+                        e
+                    })?;
+                Ok(Self {
+                    mode: Mode::Threaded(Some(event_thread)),
+                    x_handle,
+                    shared,
+                    info,
+                    logical_units,
+                    size_mutex: Mutex::new(size),
+                })
             }
-
-            // Create a color map
-            let color_map_id = thread_x_handle.generate_id();
-            xcb::create_colormap(
-                thread_x_handle.conn_ref(),
-                xcb::COLORMAP_ALLOC_NONE as u8,
-                color_map_id,
-                parent_id,
-                visual_info_id,
-            );
-
-            // Create the actual window
-            #[rustfmt::skip]
-            let window_options = &[
-                (xcb::CW_BACK_PIXEL, screen.white_pixel()),
-                (xcb::CW_BORDER_PIXEL, screen.black_pixel()),
-                (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE | xcb::EVENT_MASK_BUTTON_PRESS),
-                (xcb::CW_COLORMAP, color_map_id)
-            ];
-            let window_id = thread_x_handle.generate_id();
-            xcb::create_window(
-                thread_x_handle.conn_ref(),
-                visual_info_depth,
-                window_id,
-                parent_id,
-                0,
-                0,
-                size.0 as u16,
-                size.1 as u16,
-                0,
-                xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
-                visual_info_id,
-                window_options,
-            );
-            // Put the window_id into the mutex in a scope so it's not locked forever
-            {
-                let mut wid = thread_window_id_mutex.lock().unwrap();
-                *wid = window_id;
+            EventLoopMode::HostDriven => {
+                // The host pumps our events on its own thread, so this window gets a connection
+                // of its own.
+                let x_handle = Arc::new(x_handle::XHandle::new()?);
+                let shared = Arc::new(Shared::new(
+                    Arc::new(Waker::new()?),
+                    options.parameters.clone(),
+                    options.host_resize.clone(),
+                ));
+                let context = WindowContext::new(
+                    x_handle.clone(),
+                    shared.clone(),
+                    state,
+                    parent_id,
+                    size,
+                    &options,
+                )?;
+                Ok(Self {
+                    info: context.info(),
+                    mode: Mode::HostDriven(Some(Box::new(context))),
+                    x_handle,
+                    shared,
+                    logical_units: options.logical_units,
+                    size_mutex: Mutex::new(size),
+                })
             }
+        }
+    }
+
+}
+
+impl WindowImpl for PlatformWindow {
+    fn new(
+        state: Box<dyn GuiState>,
+        parent: *mut c_void,
+        size: (u32, u32),
+        options: WindowOptions,
+    ) -> Result<Self, WindowError> {
+        Self::open(&mut Some(state), parent, size, options)
+    }
 
-            // Don't need this visual info anymore.
-            unsafe { xlib::XFree(visual_info as *mut c_void) };
-
-            // Allow deleting the window via the "protocols" / "delete_window" atoms (??? ...magic)
-            let protocols_atom = thread_x_handle.make_cookie_atom(false, "WM_PROTOCOLS");
-            let delete_window_atom = thread_x_handle.make_cookie_atom(false, "WM_DELETE_WINDOW");
-            let protocols = [protocols_atom];
-            xcb::change_property(
-                thread_x_handle.conn_ref(),
-                xcb::PROP_MODE_REPLACE as u8,
-                window_id,
-                protocols_atom,
-                xcb::ATOM_ATOM,
-                32,
-                &protocols,
-            );
-            // Put the protocols_atom into the mutex in a scope so it's not locked forever
-            {
-                let mut pa = thread_protocols_atom_mutex.lock().unwrap();
-                *pa = protocols_atom;
+    fn resize(&self, size: (u32, u32)) {
+        self.configure(self.to_physical_size(size));
+        *self.size_mutex.lock().unwrap() = size;
+    }
+
+    fn request_resize(&self, size: (u32, u32)) -> bool {
+        if !self.shared.ask_host_to_resize(self.to_physical_size(size)) {
+            info!("Host refused to resize the editor to {:?}", size);
+            return false;
+        }
+        self.resize(size);
+        true
+    }
+
+    fn show(&self) {
+        xcb::map_window(self.x_handle.conn_ref(), self.window_id());
+        self.x_handle.flush();
+    }
+
+    fn hide(&self) {
+        xcb::unmap_window(self.x_handle.conn_ref(), self.window_id());
+        self.x_handle.flush();
+    }
+
+    fn invalidate(&self) {
+        self.shared.request_redraw();
+    }
+
+    fn set_timer(&self, id: u64, delay: Duration, repeat: bool) {
+        self.shared.set_timer(id, delay, repeat);
+    }
+
+    fn cancel_timer(&self, id: u64) {
+        self.shared.cancel_timer(id);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        // Ask the X server, in case something else (the host, the window manager) resized us.
+        let cookie = xcb::get_geometry(self.x_handle.conn_ref(), self.window_id());
+        match cookie.get_reply() {
+            Ok(geometry) => {
+                self.to_logical_size((geometry.width() as u32, geometry.height() as u32))
             }
-            // Put the delete_window_atom into the mutex in a scope so it's not locked forever
-            {
-                let mut dwa = thread_delete_window_atom_mutex.lock().unwrap();
-                *dwa = delete_window_atom;
+            Err(_) => {
+                warn!("Could not get window geometry; returning the last requested size");
+                *self.size_mutex.lock().unwrap()
             }
+        }
+    }
 
-            // Okay, now the fun part. Make an OpenGL context!
-            let gl_context = gl_utils::create_gl_context(thread_x_handle.clone(), glx_frame_buffer_config);
+    fn window_id(&self) -> u32 {
+        self.info.window_id
+    }
+
+    fn raw_window(&self) -> RawWindow {
+        self.x_handle.raw_window(self.info.window_id, self.info.visual_id)
+    }
+
+    fn backend(&self) -> Backend {
+        self.info.backend
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.shared.scale_factor()
+    }
+
+    fn swap_interval(&self) -> Option<i32> {
+        self.shared.swap_interval()
+    }
+
+    fn panic_message(&self) -> Option<String> {
+        self.shared.panic_message()
+    }
+
+    fn proxy(&self) -> PlatformProxy {
+        PlatformProxy::new(self.shared.clone())
+    }
+
+    fn connection_fd(&self) -> Option<RawFd> {
+        match self.mode {
+            Mode::HostDriven(Some(_)) => Some(self.x_handle.connection_fd()),
+            _ => None,
+        }
+    }
+
+    fn wake_fd(&self) -> Option<RawFd> {
+        match self.mode {
+            Mode::HostDriven(Some(_)) => Some(self.shared.waker().read_fd()),
+            _ => None,
+        }
+    }
 
-            // Map (display) the window.
-            xcb::map_window(thread_x_handle.conn_ref(), window_id);
-            thread_x_handle.flush();
-            unsafe {
-                xlib::XSync(thread_x_handle.raw_display(), xlib::False);
+    fn next_timeout(&self) -> Option<Duration> {
+        match &self.mode {
+            Mode::HostDriven(Some(context)) => context.wait_timeout(),
+            _ => None,
+        }
+    }
+
+    fn pump_events(&mut self) {
+        if let Mode::Threaded(Some(_)) = &self.mode {
+            // Resizes the event thread couldn't ask the host for itself.
+            if let Some(size) = self.shared.take_deferred_host_resize() {
+                if self.shared.ask_host_to_resize(size) {
+                    self.configure(size);
+                    *self.size_mutex.lock().unwrap() = self.to_logical_size(size);
+                } else {
+                    info!("Host refused to resize the editor to {:?}", size);
+                }
             }
+        }
+        if let Mode::HostDriven(Some(context)) = &mut self.mode {
+            self.shared.waker().drain();
+            while let Some(ev) = self.x_handle.poll_for_event() {
+                context.handle_event(&ev);
+            }
+            context.handle_pending();
+            // The round trips in there (drawing, scale queries, ...) may have read events into
+            // XCB's queue. The fd won't become readable for those, so handle them now.
+            while let Some(ev) = self.x_handle.poll_for_event() {
+                context.handle_event(&ev);
+            }
+            self.x_handle.flush();
+        }
+    }
 
-            // Now we can finally let the main thread know that it's safe to continue and
-            // return from new().
-            spawned.safe_to_continue();
-
-            // Handle all window events
-            // We'll tell the main thread that it's safe to continue after the first "Expose" (draw) event.
-            handle_events(
-                thread_x_handle.clone(),
-                spawned,
-                window_id,
-                gl_context,
-                protocols_atom,
-                delete_window_atom,
-                state,
-            );
-
-            // After the event handler stops, it's time to destroy everything we've created.
-            // Goodbye, cruel world! :(
-            unsafe { glx::glXDestroyContext(thread_x_handle.raw_display(), gl_context); }
-            xcb::destroy_window(thread_x_handle.conn_ref(), window_id);
-            xcb::free_colormap(thread_x_handle.conn_ref(), color_map_id);
-            thread_x_handle.flush();
-            info!("Thread dead.");
-        });
-
-        // Wait for the thread tell us it's safe to continue
-        info!("Waiting for spawned thread to finish...");
-        spawner.wait_for_spawned();
-        info!("Spawned thread ready. Returning from new().");
-
-        Self {
-            t: Some(t),
-            x_handle,
-            window_id_mutex,
-            protocols_atom_mutex,
-            delete_window_atom_mutex,
+    fn handle_host_key(&mut self, key_event: KeyEvent) {
+        match &mut self.mode {
+            Mode::HostDriven(Some(context)) => context.handle_host_key(key_event),
+            _ => info!("Dropping host key event; the GuiState lives on the GUI thread"),
         }
     }
-}
 
-impl Drop for PlatformWindow {
-    fn drop(&mut self) {
-        info!("Window::drop()");
+    fn close(&mut self) -> Option<Box<dyn GuiState>> {
+        self.shared.close();
+        match &mut self.mode {
+            Mode::Threaded(event_thread) => event_thread.take()?.close_window(self.info.window_id),
+            Mode::HostDriven(context) => context.take().map(|context| context.into_state()),
+        }
+    }
+}
 
-        // Send a delete_window CLIENT_MESSAGE event to the event handler to tell it
-        // to stop processing events.
-        // TODO: I just referenced some random example; I'm not sure how you're actually supposed to
-        // construct these things. Any X11 experts in the house? If so... sorry about this entire
-        // codebase in general.
-        let window_id = *self.window_id_mutex.lock().unwrap();
-        let protocols_atom = *self.protocols_atom_mutex.lock().unwrap();
-        let delete_window_atom = *self.delete_window_atom_mutex.lock().unwrap();
-        let mut data = [0x00u32; 5];
-        data[0] = delete_window_atom;
-        let message_data = xcb::ffi::xproto::xcb_client_message_data_t {
-            data: unsafe { std::mem::transmute::<[u32; 5], [u8; 20]>(data) },
-        };
-        let delete_window_event = xcb::ffi::xproto::xcb_client_message_event_t {
-            response_type: xcb::ffi::xproto::XCB_CLIENT_MESSAGE,
-            format: 32,
-            window: window_id,
-            type_: protocols_atom,
-            data: message_data,
-            sequence: 0,
-        };
-        self.x_handle.send_event(
-            window_id,
-            &delete_window_event as *const xcb::ffi::xproto::xcb_client_message_event_t
-                as *const i8,
+impl PlatformWindow {
+    /// Resize the X window to `size`, in pixels.
+    fn configure(&self, (width, height): (u32, u32)) {
+        xcb::configure_window(
+            self.x_handle.conn_ref(),
+            self.window_id(),
+            &[
+                (xcb::CONFIG_WINDOW_WIDTH as u16, width),
+                (xcb::CONFIG_WINDOW_HEIGHT as u16, height),
+            ],
         );
         self.x_handle.flush();
+    }
 
-        // Join the thread to make sure it's dead
-        if let Some(handle) = self.t.take() {
-            handle.join().unwrap_or_else(|_| {
-                // TODO: what do I do in this case?
-                panic!("Thread join failed???");
-            });
+    fn to_physical_size(&self, size: (u32, u32)) -> (u32, u32) {
+        if self.logical_units {
+            scale::to_physical(size, self.shared.scale_factor())
         } else {
-            // TODO: what do I do in this case? probably just ignore it, I guess.
-            panic!("Thread handle didn't exist anymore when attempting to join...?");
+            size
         }
     }
-}
-fn handle_events(
-    x_handle: Arc<x_handle::XHandle>,
-    gate: thread_gate::Spawned,
-    window_id: u32,
-    gl_context: *mut x11::glx::__GLXcontextRec,
-    protocols_atom: u32,
-    delete_window_atom: u32,
-    mut state: Box<dyn GuiState>,
-) {
-    let mut first_draw = false;
-    info!("Event loop begin");
-    loop {
-        //info!("Waiting for event");
-        if let Some(ev) = x_handle.wait_for_event() {
-            let ev_type = ev.response_type() & !0x80;
-            match ev_type {
-                xcb::EXPOSE => {
-                    // X11's draw event.
-                    unsafe {
-                        glx::glXMakeCurrent(x_handle.raw_display(), window_id as xlib::XID, gl_context);
-                    }
-                    state.draw();
-                    unsafe {
-                        gl_utils::check_gl_error();
-                        glx::glXSwapBuffers(x_handle.raw_display(), window_id as xlib::XID);
-                        glx::glXMakeCurrent(x_handle.raw_display(), 0, null_mut());
-                    };
-                    // If this is the first time we drew the window,
-                    if !first_draw {
-                        first_draw = true;
-
-                    }
-                }
-                xcb::BUTTON_PRESS => {
-                    // X11's mouse click (down) event.
-                    let button_press_event =
-                        unsafe { xcb::cast_event::<xcb::ButtonPressEvent>(&ev) };
-
-                    let x = button_press_event.event_x() as i32;
-                    let y = button_press_event.event_y() as i32;
-                    let mouse_button = button_press_event.detail(); // TODO: turn into enum
-
-                    // TODO: just make a translation function somewhere else.
-                    match mouse_button {
-                        1 => state.handle_mouse(MouseEvent::LeftMouseButtonDown, x, y),
-                        2 => state.handle_mouse(MouseEvent::MiddleMouseButtonDown, x, y),
-                        3 => state.handle_mouse(MouseEvent::RightMouseButtonDown, x, y),
-                        4 => state.handle_mouse(MouseEvent::ScrollUp, x, y),
-                        5 => state.handle_mouse(MouseEvent::ScrollDown, x, y),
-                        6 => state.handle_mouse(MouseEvent::ScrollLeft, x, y),
-                        7 => state.handle_mouse(MouseEvent::ScrollRight, x, y),
-                        8 => state.handle_mouse(MouseEvent::BackMouseButtonDown, x, y),
-                        9 => state.handle_mouse(MouseEvent::ForwardMouseButtonDown, x, y),
-                        _ => info!("Unknown mouse button: {} ({}, {})", mouse_button, x, y),
-                    }
 
-                }
-                xcb::CLIENT_MESSAGE => {
-                    info!("client_message");
-                    let client_message_event =
-                        unsafe { xcb::cast_event::<xcb::ClientMessageEvent>(&ev) };
-                    if client_message_event.type_() == protocols_atom
-                        && client_message_event.format() == 32
-                        {
-                            let protocol = client_message_event.data().data32()[0];
-                            if protocol == delete_window_atom {
-                                info!("delete_window message received. Killing thread!");
-                                break;
-                            }
-                        }
-                    info!("Uhh.. Some other client_message I guess.");
-                }
-                _ => {
-                    info!("some other event");
-                }
-            }
+    fn to_logical_size(&self, size: (u32, u32)) -> (u32, u32) {
+        if self.logical_units {
+            scale::to_logical(size, self.shared.scale_factor())
+        } else {
+            size
         }
     }
 }
 
+impl Drop for PlatformWindow {
+    fn drop(&mut self) {
+        info!("Window::drop()");
+        self.close();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gui_state::{Frame, Modifiers, MouseEvent};
+
+    struct TestState;
+
+    impl GuiState for TestState {
+        fn draw(&mut self, _gl: &glow::Context, _frame: &Frame) {}
+        fn handle_mouse(&mut self, _mouse_event: MouseEvent, _x: i32, _y: i32, _modifiers: Modifiers, _time: u32) {}
+    }
+
+    fn open_test_window() -> PlatformWindow {
+        PlatformWindow::new(
+            Box::new(TestState),
+            std::ptr::null_mut(),
+            (400, 300),
+            WindowOptions::default(),
+        ).unwrap()
+    }
 
     // run with `./run-sanitizer-tests.sh` in the root of the crate (tested on Linux).
     // No output = good.
@@ -332,13 +344,13 @@ mod tests {
     #[ignore]
     fn sanitizer_tests() {
         use std::{thread, time};
-        use std::ffi::c_void;
         use std::fs::File;
-        use log::*;
 
         // Set up a logger so we can see what's going on in the VST
-        let mut logger_config = simplelog::Config::default();
-        logger_config.time_format = Some("%H:%M:%S%.6f");
+        let logger_config = simplelog::Config {
+            time_format: Some("%H:%M:%S%.6f"),
+            ..Default::default()
+        };
         simplelog::CombinedLogger::init(vec![simplelog::WriteLogger::new(
             simplelog::LevelFilter::max(),
             logger_config,
@@ -346,10 +358,9 @@ mod tests {
         )]).unwrap();
         info!("====================================================================");
 
-        let mut window = Some(PlatformWindow::new(0 as *mut c_void));
+        let window = open_test_window();
         thread::sleep(time::Duration::from_millis(2000));
-        window = None;
-
+        drop(window);
     }
 
     #[test]
@@ -361,15 +372,15 @@ mod tests {
     // TODO: I've noticed a few times where it takes a while to draw the window, is there an issue
     // somewhere?
     fn window_open_close_no_deadlock() {
-        use std::ffi::c_void;
         use std::{thread, time};
         use std::fs::File;
-        use log::*;
         use rand::prelude::*;
 
         // Set up a logger so we can see what's going on in the test
-        let mut logger_config = simplelog::Config::default();
-        logger_config.time_format = Some("%H:%M:%S%.6f");
+        let logger_config = simplelog::Config {
+            time_format: Some("%H:%M:%S%.6f"),
+            ..Default::default()
+        };
         simplelog::CombinedLogger::init(vec![simplelog::WriteLogger::new(
             simplelog::LevelFilter::max(),
             logger_config,
@@ -382,32 +393,130 @@ mod tests {
         // Run a bunch of opens/closes as fast as possible
         for i in 0..1000 {
             info!("{}", i);
-            let mut window = Some(PlatformWindow::new(0 as *mut c_void));
-            window = None;
+            let window = open_test_window();
+            drop(window);
         }
 
         // Run a bunch of opens/closes with 1-10 ms delay
         for i in 0..1000 {
             info!("{}", i);
-            let mut window = Some(PlatformWindow::new(0 as *mut c_void));
+            let window = open_test_window();
             thread::sleep(time::Duration::from_millis(rng.gen_range(1, 11)));
-            window = None;
+            drop(window);
         }
 
         // Run a bunch of opens/closes with 10-100 ms delay
         for i in 0..1000 {
             info!("{}", i);
-            let mut window = Some(PlatformWindow::new(0 as *mut c_void));
+            let window = open_test_window();
             thread::sleep(time::Duration::from_millis(rng.gen_range(10, 101)));
-            window = None;
+            drop(window);
         }
 
         // Run a bunch of opens/closes with 100-1000 ms delay
         for i in 0..1000 {
             info!("{}", i);
-            let mut window = Some(PlatformWindow::new(0 as *mut c_void));
+            let window = open_test_window();
             thread::sleep(time::Duration::from_millis(rng.gen_range(100, 1001)));
-            window = None;
+            drop(window);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    // run with `cargo test host_driven_pump_events -- --ignored`
+    fn host_driven_pump_events() {
+        use std::{thread, time};
+
+        let options = WindowOptions {
+            event_loop: EventLoopMode::HostDriven,
+            ..Default::default()
+        };
+        let mut window =
+            PlatformWindow::new(Box::new(TestState), std::ptr::null_mut(), (400, 300), options)
+                .unwrap();
+        assert!(window.connection_fd().is_some());
+
+        // A proxy can't write to the connection; the host has to watch the wake-up fd too.
+        let wake_fd = window.wake_fd().unwrap();
+        let readable = || {
+            let mut fds = [libc::pollfd { fd: wake_fd, events: libc::POLLIN, revents: 0 }];
+            unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) == 1 }
+        };
+        assert!(PlatformProxy::new(window.shared.clone()).request_redraw());
+        assert!(readable());
+        window.pump_events();
+        assert!(!readable());
+
+        // Pretend to be a host calling effEditIdle at ~30 Hz.
+        for _ in 0..60 {
+            window.pump_events();
+            thread::sleep(time::Duration::from_millis(33));
+        }
+    }
+
+    #[test]
+    #[ignore]
+    // run with `cargo test software_backend -- --ignored`
+    fn software_backend() {
+        use std::{thread, time};
+
+        let options = WindowOptions {
+            backend: Backend::Software,
+            ..Default::default()
+        };
+        let window =
+            PlatformWindow::new(Box::new(TestState), std::ptr::null_mut(), (400, 300), options)
+                .unwrap();
+        assert_eq!(window.backend(), Backend::Software);
+        window.invalidate();
+        thread::sleep(time::Duration::from_millis(500));
+    }
+
+    #[test]
+    #[ignore]
+    // run with `cargo test raw_backend -- --ignored`
+    fn raw_backend() {
+        use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+
+        let options = WindowOptions {
+            backend: Backend::None,
+            ..Default::default()
+        };
+        let window =
+            PlatformWindow::new(Box::new(TestState), std::ptr::null_mut(), (400, 300), options)
+                .unwrap();
+        assert_eq!(window.backend(), Backend::None);
+        let raw_window = window.raw_window();
+        match raw_window.raw_window_handle() {
+            RawWindowHandle::Xlib(handle) => {
+                assert_eq!(handle.window, window.window_id() as std::os::raw::c_ulong);
+                assert_ne!(handle.visual_id, 0);
+            }
+            handle => panic!("expected an Xlib handle, got {:?}", handle),
+        }
+        assert!(matches!(raw_window.xcb_window_handle(), RawWindowHandle::Xcb(_)));
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "egl")]
+    // run with `xvfb-run cargo test --features egl egl_backend -- --ignored`. Under Xvfb, Mesa
+    // falls back to llvmpipe, so this needs no GPU.
+    fn egl_backend() {
+        use crate::window::EglApi;
+
+        for api in [EglApi::OpenGl, EglApi::Gles2, EglApi::Gles3] {
+            let options = WindowOptions {
+                backend: Backend::Egl(api),
+                ..Default::default()
+            };
+            let window =
+                PlatformWindow::new(Box::new(TestState), std::ptr::null_mut(), (400, 300), options)
+                    .unwrap();
+            assert_eq!(window.backend(), Backend::Egl(api));
+            window.invalidate();
+            std::thread::sleep(std::time::Duration::from_millis(200));
         }
     }
 
@@ -0,0 +1,282 @@
+// State shared between a window's event loop and the `WindowProxy`s handed out to other threads.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::parameters::ParameterQueue;
+use crate::window::HostResizeCallback;
+use super::clock::Clock;
+use super::timers::Timers;
+use super::waker::Waker;
+
+/// How often the event loop checks the `ParameterQueue`, if there is one. The audio thread can't
+/// wake us up (that would take a system call), so we poll at roughly the display's frame rate.
+const PARAMETER_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+pub struct Shared {
+    messages: Mutex<VecDeque<Box<dyn Any + Send>>>,
+    redraw_requested: AtomicBool,
+    closed: AtomicBool,
+    waker: Arc<Waker>,
+    parameters: Option<Arc<ParameterQueue>>,
+    host_resize: Option<HostResizeCallback>,
+    resize_requested: Mutex<Option<(u32, u32)>>,
+    // In pixels, waiting for the host's thread.
+    deferred_host_resize: Mutex<Option<(u32, u32)>>,
+    // The window's scale factor, as f64 bits.
+    scale_factor: AtomicU64,
+    swap_interval: Mutex<Option<i32>>,
+    timers: Mutex<Timers>,
+    // Why the GuiState was poisoned, if it was.
+    panic_message: Mutex<Option<String>>,
+    clock: Clock,
+}
+
+impl Shared {
+    pub fn new(
+        waker: Arc<Waker>,
+        parameters: Option<Arc<ParameterQueue>>,
+        host_resize: Option<HostResizeCallback>,
+    ) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::new()),
+            redraw_requested: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            waker,
+            parameters,
+            host_resize,
+            resize_requested: Mutex::new(None),
+            deferred_host_resize: Mutex::new(None),
+            scale_factor: AtomicU64::new(1.0f64.to_bits()),
+            swap_interval: Mutex::new(None),
+            timers: Mutex::new(Timers::default()),
+            panic_message: Mutex::new(None),
+            clock: Clock::default(),
+        }
+    }
+
+    /// Use `clock` instead of the system clock.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        f64::from_bits(self.scale_factor.load(Ordering::Relaxed))
+    }
+
+    pub fn set_scale_factor(&self, scale_factor: f64) {
+        self.scale_factor.store(scale_factor.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn swap_interval(&self) -> Option<i32> {
+        *self.swap_interval.lock().unwrap()
+    }
+
+    pub fn set_swap_interval(&self, swap_interval: Option<i32>) {
+        *self.swap_interval.lock().unwrap() = swap_interval;
+    }
+
+    pub fn panic_message(&self) -> Option<String> {
+        self.panic_message.lock().unwrap().clone()
+    }
+
+    pub fn set_panic_message(&self, message: String) {
+        self.panic_message.lock().unwrap().get_or_insert(message);
+    }
+
+    /// Ask the host for `size`, if we know how. Returns whether the window should be resized.
+    pub fn ask_host_to_resize(&self, size: (u32, u32)) -> bool {
+        match &self.host_resize {
+            Some(host_resize) => host_resize.call(size),
+            None => true,
+        }
+    }
+
+    pub fn has_host_resize(&self) -> bool {
+        self.host_resize.is_some()
+    }
+
+    /// Leave asking the host for `size` to its own thread.
+    pub fn defer_host_resize(&self, size: (u32, u32)) {
+        *self.deferred_host_resize.lock().unwrap() = Some(size);
+    }
+
+    /// The latest size passed to `defer_host_resize()` since the last call, if any.
+    pub fn take_deferred_host_resize(&self) -> Option<(u32, u32)> {
+        self.deferred_host_resize.lock().unwrap().take()
+    }
+
+    /// The latest size asked for through a proxy since the last call, if any.
+    pub fn take_resize_request(&self) -> Option<(u32, u32)> {
+        self.resize_requested.lock().unwrap().take()
+    }
+
+    pub fn waker(&self) -> &Waker {
+        &self.waker
+    }
+
+    pub fn parameters(&self) -> Option<&ParameterQueue> {
+        self.parameters.as_deref()
+    }
+
+    /// How long the event loop may block waiting for events, as far as parameters and timers go.
+    /// `None` means forever.
+    pub fn wait_timeout(&self) -> Option<Duration> {
+        let parameters = self.parameters.as_ref().map(|_| PARAMETER_POLL_INTERVAL);
+        let timers = self
+            .next_timer_deadline()
+            .map(|deadline| deadline.saturating_duration_since(self.now()));
+        min_timeout(parameters, timers)
+    }
+
+    pub fn set_timer(&self, id: u64, delay: Duration, repeat: bool) {
+        let now = self.now();
+        self.timers.lock().unwrap().set(id, now, delay, repeat);
+        // The event loop may be waiting for a later deadline.
+        self.waker.wake();
+    }
+
+    pub fn cancel_timer(&self, id: u64) {
+        self.timers.lock().unwrap().cancel(id);
+    }
+
+    /// The ids of the timers that are due, earliest first.
+    pub fn take_expired_timers(&self) -> Vec<u64> {
+        let now = self.now();
+        self.timers.lock().unwrap().take_expired(now)
+    }
+
+    pub fn next_timer_deadline(&self) -> Option<Instant> {
+        self.timers.lock().unwrap().next_deadline()
+    }
+
+    /// Take all the messages posted so far.
+    pub fn take_messages(&self) -> VecDeque<Box<dyn Any + Send>> {
+        std::mem::take(&mut *self.messages.lock().unwrap())
+    }
+
+    /// Whether a redraw was requested since the last call. Resets the request.
+    pub fn take_redraw_request(&self) -> bool {
+        self.redraw_requested.swap(false, Ordering::AcqRel)
+    }
+
+    pub fn request_redraw(&self) {
+        self.redraw_requested.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Called when the window goes away; anything posted after this is dropped. The event loop
+    /// stops handling the window's events and pending work until it's destroyed.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.messages.lock().unwrap().clear();
+        self.waker.wake();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Clone)]
+pub struct PlatformProxy {
+    shared: Arc<Shared>,
+}
+
+impl PlatformProxy {
+    pub fn new(shared: Arc<Shared>) -> Self {
+        Self { shared }
+    }
+
+    pub fn post_message(&self, message: Box<dyn Any + Send>) -> bool {
+        {
+            // Check under the lock, so we can't race with close() clearing the queue.
+            let mut messages = self.shared.messages.lock().unwrap();
+            if self.shared.closed.load(Ordering::Acquire) {
+                return false;
+            }
+            messages.push_back(message);
+        }
+        self.shared.waker.wake();
+        true
+    }
+
+    pub fn request_redraw(&self) -> bool {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        self.shared.request_redraw();
+        true
+    }
+
+    pub fn request_resize(&self, size: (u32, u32)) -> bool {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        *self.shared.resize_requested.lock().unwrap() = Some(size);
+        self.shared.waker.wake();
+        true
+    }
+
+    pub fn set_timer(&self, id: u64, delay: Duration, repeat: bool) -> bool {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        self.shared.set_timer(id, delay, repeat);
+        true
+    }
+
+    pub fn cancel_timer(&self, id: u64) -> bool {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        self.shared.cancel_timer(id);
+        true
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+/// The shorter of two timeouts, where `None` means forever.
+pub fn min_timeout(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_dropped_after_close() {
+        let shared = Arc::new(Shared::new(Arc::new(Waker::new().unwrap()), None, None));
+        let proxy = PlatformProxy::new(shared.clone());
+
+        assert!(proxy.post_message(Box::new(1u32)));
+        assert!(proxy.request_redraw());
+        let messages = shared.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].downcast_ref::<u32>(), Some(&1));
+        assert!(shared.take_redraw_request());
+        assert!(!shared.take_redraw_request());
+
+        shared.close();
+        assert!(proxy.is_closed());
+        assert!(!proxy.post_message(Box::new(2u32)));
+        assert!(!proxy.request_redraw());
+        assert!(shared.take_messages().is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+// HiDPI scale factor detection.
+//
+// X has no single notion of a scale factor, so we look in the places desktops actually put one,
+// in this order:
+//
+// 1. XSETTINGS `Gdk/WindowScalingFactor` (GNOME and friends set this to 2 on HiDPI screens)
+// 2. The `Xft.dpi` X resource (what `xrdb` sets; KDE, i3 setups, etc.)
+// 3. XSETTINGS `Xft/DPI`
+// 4. RandR: the physical size of the monitor the window is on
+//
+// The first three apply to the whole desktop. Only RandR can tell monitors apart, so a window
+// moving to another monitor only changes the scale factor when we get it from there.
+
+use log::*;
+
+use super::x_handle::XHandle;
+
+/// The DPI that counts as a scale factor of 1.
+const BASE_DPI: f64 = 96.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Monitor {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    scale: f64,
+}
+
+impl Monitor {
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// Keeps track of the desktop's scale factor settings and the monitors' scale factors, and tells
+/// us which events mean they might have changed.
+pub struct ScaleDetector {
+    root: u32,
+    xsettings_selection_atom: u32,
+    xsettings_settings_atom: u32,
+    xsettings_owner: u32,
+    // The RandR extension's first event code, if RandR 1.3 is available.
+    randr_first_event: Option<u8>,
+    // Set from XSETTINGS or Xft.dpi; overrides the per-monitor scale.
+    desktop_scale: Option<f64>,
+    monitors: Vec<Monitor>,
+}
+
+impl ScaleDetector {
+    pub fn new(x_handle: &XHandle, root: u32) -> Self {
+        let conn = x_handle.conn_ref();
+        let atom = |name| x_handle.make_cookie_atom(false, name).unwrap_or(xcb::NONE);
+
+        // RESOURCE_MANAGER (Xft.dpi) lives on the root window.
+        xcb::change_window_attributes(
+            conn,
+            root,
+            &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)],
+        );
+
+        let randr_first_event = match conn.get_extension_data(xcb::randr::id()) {
+            Some(data) if data.present() => {
+                let version = xcb::randr::query_version(conn, 1, 3).get_reply();
+                match version {
+                    Ok(version) if (version.major_version(), version.minor_version()) >= (1, 3) => {
+                        xcb::randr::select_input(
+                            conn,
+                            root,
+                            (xcb::randr::NOTIFY_MASK_SCREEN_CHANGE
+                                | xcb::randr::NOTIFY_MASK_CRTC_CHANGE) as u16,
+                        );
+                        Some(data.first_event())
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        let mut detector = Self {
+            root,
+            xsettings_selection_atom: atom(&format!("_XSETTINGS_S{}", x_handle.screen_num())),
+            xsettings_settings_atom: atom("_XSETTINGS_SETTINGS"),
+            xsettings_owner: xcb::NONE,
+            randr_first_event,
+            desktop_scale: None,
+            monitors: Vec::new(),
+        };
+        detector.refresh_desktop_scale(x_handle);
+        detector.refresh_monitors(x_handle);
+        detector
+    }
+
+    /// The scale factor at `(x, y)` in root window coordinates.
+    pub fn scale_at(&self, x: i32, y: i32) -> f64 {
+        if let Some(scale) = self.desktop_scale {
+            return scale;
+        }
+        self.monitors
+            .iter()
+            .find(|monitor| monitor.contains(x, y))
+            .map_or(1.0, |monitor| monitor.scale)
+    }
+
+    /// Look at an event and update our settings if it's one that could change them. Returns `true`
+    /// if it was, meaning the scale factor should be checked again.
+    pub fn handle_event(&mut self, x_handle: &XHandle, ev: &xcb::GenericEvent) -> bool {
+        let ev_type = ev.response_type() & !0x80;
+        match ev_type {
+            xcb::PROPERTY_NOTIFY => {
+                let property_event = unsafe { xcb::cast_event::<xcb::PropertyNotifyEvent>(ev) };
+                let window = property_event.window();
+                let atom = property_event.atom();
+                if (window == self.root && atom == xcb::ATOM_RESOURCE_MANAGER)
+                    || (window == self.xsettings_owner && atom == self.xsettings_settings_atom)
+                {
+                    self.refresh_desktop_scale(x_handle);
+                    return true;
+                }
+                false
+            }
+            xcb::DESTROY_NOTIFY => {
+                // The settings daemon went away (or is being restarted).
+                let destroy_event = unsafe { xcb::cast_event::<xcb::DestroyNotifyEvent>(ev) };
+                let window = destroy_event.window();
+                if self.xsettings_owner != xcb::NONE && window == self.xsettings_owner {
+                    self.refresh_desktop_scale(x_handle);
+                    return true;
+                }
+                false
+            }
+            _ => match self.randr_first_event {
+                Some(first_event)
+                    if ev_type == first_event + xcb::randr::SCREEN_CHANGE_NOTIFY
+                        || ev_type == first_event + xcb::randr::NOTIFY =>
+                {
+                    self.refresh_monitors(x_handle);
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+
+    fn refresh_desktop_scale(&mut self, x_handle: &XHandle) {
+        let conn = x_handle.conn_ref();
+
+        self.xsettings_owner = xcb::get_selection_owner(conn, self.xsettings_selection_atom)
+            .get_reply()
+            .map(|reply| reply.owner())
+            .unwrap_or(xcb::NONE);
+        let xsettings = if self.xsettings_owner != xcb::NONE {
+            // Hear about changes to the settings, and about the owner going away.
+            xcb::change_window_attributes(
+                conn,
+                self.xsettings_owner,
+                &[(
+                    xcb::CW_EVENT_MASK,
+                    xcb::EVENT_MASK_PROPERTY_CHANGE | xcb::EVENT_MASK_STRUCTURE_NOTIFY,
+                )],
+            );
+            read_property(x_handle, self.xsettings_owner, self.xsettings_settings_atom)
+                .map(|data| parse_xsettings(&data))
+                .unwrap_or_default()
+        } else {
+            XSettings::default()
+        };
+        let xft_dpi = read_property(x_handle, self.root, xcb::ATOM_RESOURCE_MANAGER)
+            .and_then(|data| parse_xft_dpi(&String::from_utf8_lossy(&data)));
+
+        self.desktop_scale = xsettings
+            .window_scaling_factor
+            .filter(|factor| *factor > 0)
+            .map(|factor| factor as f64)
+            .or_else(|| xft_dpi.map(|dpi| dpi / BASE_DPI))
+            .or_else(|| xsettings.xft_dpi.map(|dpi| dpi as f64 / 1024.0 / BASE_DPI))
+            .filter(|scale| *scale > 0.0);
+        debug!("Desktop scale factor: {:?}", self.desktop_scale);
+    }
+
+    fn refresh_monitors(&mut self, x_handle: &XHandle) {
+        self.monitors.clear();
+        if self.randr_first_event.is_none() {
+            return;
+        }
+
+        let conn = x_handle.conn_ref();
+        let cookie = xcb::randr::get_screen_resources_current(conn, self.root);
+        let resources = match cookie.get_reply() {
+            Ok(resources) => resources,
+            Err(_) => return,
+        };
+        let timestamp = resources.config_timestamp();
+        for &crtc in resources.crtcs() {
+            let crtc_info = match xcb::randr::get_crtc_info(conn, crtc, timestamp).get_reply() {
+                Ok(crtc_info) => crtc_info,
+                Err(_) => continue,
+            };
+            let output = match crtc_info.outputs().first() {
+                Some(&output) => output,
+                // Not driving any monitor.
+                None => continue,
+            };
+            let mm_width = xcb::randr::get_output_info(conn, output, timestamp)
+                .get_reply()
+                .map(|output_info| output_info.mm_width())
+                .unwrap_or(0);
+            self.monitors.push(Monitor {
+                x: crtc_info.x() as i32,
+                y: crtc_info.y() as i32,
+                width: crtc_info.width() as i32,
+                height: crtc_info.height() as i32,
+                scale: scale_from_physical_size(crtc_info.width() as u32, mm_width),
+            });
+        }
+        debug!("Monitors: {:?}", self.monitors);
+    }
+}
+
+/// Convert a size in logical units to pixels.
+pub fn to_physical(size: (u32, u32), scale: f64) -> (u32, u32) {
+    (
+        (size.0 as f64 * scale).round() as u32,
+        (size.1 as f64 * scale).round() as u32,
+    )
+}
+
+/// Convert a size in pixels to logical units.
+pub fn to_logical(size: (u32, u32), scale: f64) -> (u32, u32) {
+    to_physical(size, 1.0 / scale)
+}
+
+fn read_property(x_handle: &XHandle, window: u32, property: u32) -> Option<Vec<u8>> {
+    if property == xcb::NONE {
+        return None;
+    }
+    // Both properties are small; 64K (in 32 bit units) is plenty.
+    let cookie = xcb::get_property(
+        x_handle.conn_ref(),
+        false,
+        window,
+        property,
+        xcb::GET_PROPERTY_TYPE_ANY,
+        0,
+        0x10000,
+    );
+    match cookie.get_reply() {
+        Ok(reply) if reply.format() == 8 => Some(reply.value::<u8>().to_vec()),
+        _ => None,
+    }
+}
+
+/// Find `Xft.dpi` in the resource database string from RESOURCE_MANAGER.
+fn parse_xft_dpi(resources: &str) -> Option<f64> {
+    resources.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim() == "Xft.dpi" {
+            value.trim().parse::<f64>().ok().filter(|dpi| *dpi > 0.0)
+        } else {
+            None
+        }
+    })
+}
+
+/// A monitor's scale factor from its width in pixels and millimeters, rounded to a quarter so
+/// slightly-off EDID data doesn't give us odd factors like 1.07.
+fn scale_from_physical_size(width: u32, mm_width: u32) -> f64 {
+    // Projectors and some virtual outputs report 0 (or nonsense like 1 cm).
+    if mm_width < 100 {
+        return 1.0;
+    }
+    let dpi = width as f64 / (mm_width as f64 / 25.4);
+    ((dpi / BASE_DPI) * 4.0).round().max(4.0) / 4.0
+}
+
+/// The XSETTINGS values we care about.
+#[derive(Debug, Default, PartialEq)]
+struct XSettings {
+    window_scaling_factor: Option<i32>,
+    /// DPI * 1024.
+    xft_dpi: Option<i32>,
+}
+
+/// Parse the `_XSETTINGS_SETTINGS` property. See the XSETTINGS spec at freedesktop.org for the
+/// format. Malformed data gives us whatever was read up to that point.
+fn parse_xsettings(data: &[u8]) -> XSettings {
+    let mut settings = XSettings::default();
+    let big_endian = match data.first() {
+        Some(0) => false,
+        Some(1) => true,
+        _ => return settings,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = data.get(offset..offset + 4)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+    let pad = |length: usize| length.div_ceil(4) * 4;
+
+    // byte order (1) + unused (3) + serial (4) + number of settings (4)
+    let count = match read_u32(8) {
+        Some(count) => count,
+        None => return settings,
+    };
+    let mut offset = 12;
+    for _ in 0..count {
+        let setting_type = match data.get(offset) {
+            Some(setting_type) => *setting_type,
+            None => break,
+        };
+        let name_length = match read_u16(offset + 2) {
+            Some(length) => length as usize,
+            None => break,
+        };
+        let name = match data.get(offset + 4..offset + 4 + name_length) {
+            Some(name) => name,
+            None => break,
+        };
+        // type (1) + unused (1) + name length (2) + name (padded) + last change serial (4)
+        offset += 4 + pad(name_length) + 4;
+
+        match setting_type {
+            // Integer
+            0 => {
+                let value = match read_u32(offset) {
+                    Some(value) => value as i32,
+                    None => break,
+                };
+                match name {
+                    b"Gdk/WindowScalingFactor" => settings.window_scaling_factor = Some(value),
+                    b"Xft/DPI" => settings.xft_dpi = Some(value),
+                    _ => {}
+                }
+                offset += 4;
+            }
+            // String
+            1 => match read_u32(offset) {
+                Some(length) => offset += 4 + pad(length as usize),
+                None => break,
+            },
+            // Color: four u16s
+            2 => offset += 8,
+            _ => {
+                warn!("Unknown XSETTINGS setting type {}", setting_type);
+                break;
+            }
+        }
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(setting_type: u8, name: &str, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![setting_type, 0];
+        bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    #[test]
+    fn xsettings_scale_and_dpi() {
+        let mut data = vec![0, 0, 0, 0];
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        let mut string_value = 5u32.to_le_bytes().to_vec();
+        string_value.extend_from_slice(b"Adw\0\0\0\0\0");
+        data.extend(setting(1, "Net/ThemeName", &string_value));
+        data.extend(setting(2, "Gtk/Color", &[0; 8]));
+        data.extend(setting(0, "Gdk/WindowScalingFactor", &2i32.to_le_bytes()));
+        data.extend(setting(0, "Xft/DPI", &(192 * 1024i32).to_le_bytes()));
+
+        assert_eq!(
+            parse_xsettings(&data),
+            XSettings { window_scaling_factor: Some(2), xft_dpi: Some(192 * 1024) }
+        );
+        // Truncated data shouldn't take us out.
+        assert_eq!(parse_xsettings(&data[..30]), XSettings::default());
+        assert_eq!(parse_xsettings(&[]), XSettings::default());
+    }
+
+    #[test]
+    fn xft_dpi_from_resources() {
+        let resources = "Xft.antialias:\t1\nXft.dpi:\t144\nXcursor.size:\t48\n";
+        assert_eq!(parse_xft_dpi(resources), Some(144.0));
+        assert_eq!(parse_xft_dpi("Xft.hinting:\t1\n"), None);
+    }
+
+    #[test]
+    fn logical_physical_round_trip() {
+        assert_eq!(to_physical((400, 300), 1.5), (600, 450));
+        assert_eq!(to_logical((600, 450), 1.5), (400, 300));
+        assert_eq!(to_logical(to_physical((333, 101), 1.25), 1.25), (333, 101));
+    }
+
+    #[test]
+    fn monitor_scale_from_physical_size() {
+        // 27" 4K
+        assert_eq!(scale_from_physical_size(3840, 597), 1.75);
+        // 24" 1080p
+        assert_eq!(scale_from_physical_size(1920, 527), 1.0);
+        assert_eq!(scale_from_physical_size(1920, 0), 1.0);
+    }
+}
@@ -0,0 +1,234 @@
+// The software backend: the GuiState draws into a `PixelBuffer`, which we copy to the window
+// through a MIT-SHM shared memory segment, or with plain PutImage requests where that's not
+// available (e.g. remote X over SSH).
+
+use std::ptr::null_mut;
+use std::sync::Arc;
+
+use log::*;
+
+use crate::pixel_buffer::PixelBuffer;
+use super::x_handle::XHandle;
+
+/// A shared memory segment attached on both our side and the X server's.
+struct ShmSegment {
+    x_handle: Arc<XHandle>,
+    seg: u32,
+    address: *mut u8,
+    size: usize,
+}
+
+impl ShmSegment {
+    fn new(x_handle: Arc<XHandle>, size: usize) -> Option<Self> {
+        let shm_id = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+        if shm_id < 0 {
+            warn!("shmget() failed: {}", std::io::Error::last_os_error());
+            return None;
+        }
+        let address = unsafe { libc::shmat(shm_id, null_mut(), 0) };
+        if address as isize == -1 {
+            warn!("shmat() failed: {}", std::io::Error::last_os_error());
+            unsafe { libc::shmctl(shm_id, libc::IPC_RMID, null_mut()) };
+            return None;
+        }
+
+        let seg = x_handle.generate_id();
+        let attached = xcb::shm::attach_checked(x_handle.conn_ref(), seg, shm_id as u32, false)
+            .request_check()
+            .is_ok();
+        // Either way, the segment goes away once everyone has detached from it.
+        unsafe { libc::shmctl(shm_id, libc::IPC_RMID, null_mut()) };
+        if !attached {
+            // Typically a remote display, which can't see our memory.
+            info!("X server could not attach the shared memory segment");
+            unsafe { libc::shmdt(address) };
+            return None;
+        }
+
+        Some(Self {
+            x_handle,
+            seg,
+            address: address as *mut u8,
+            size,
+        })
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.address, self.size) }
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        xcb::shm::detach(self.x_handle.conn_ref(), self.seg);
+        self.x_handle.flush();
+        unsafe { libc::shmdt(self.address as *const libc::c_void) };
+    }
+}
+
+pub struct SoftwareRenderer {
+    x_handle: Arc<XHandle>,
+    window_id: u32,
+    gc: u32,
+    depth: u8,
+    // Whether the server's images are most significant byte first.
+    msb_first: bool,
+    buffer: PixelBuffer,
+    use_shm: bool,
+    shm: Option<ShmSegment>,
+    // The converted image for PutImage, when we can't use shared memory.
+    scratch: Vec<u8>,
+}
+
+/// Whether we can draw to windows of this depth. We only handle 24 and 32 bit TrueColor, which is
+/// what every X server people actually use gives us.
+pub fn supports_depth(depth: u8) -> bool {
+    depth == 24 || depth == 32
+}
+
+impl SoftwareRenderer {
+    /// `depth` must be one that `supports_depth()` accepts.
+    pub fn new(x_handle: Arc<XHandle>, window_id: u32, depth: u8) -> Self {
+        let conn = x_handle.conn_ref();
+        let gc = x_handle.generate_id();
+        xcb::create_gc(conn, gc, window_id, &[(xcb::GC_GRAPHICS_EXPOSURES, 0)]);
+
+        let use_shm = match conn.get_extension_data(xcb::shm::id()) {
+            Some(data) if data.present() => xcb::shm::query_version(conn).get_reply().is_ok(),
+            _ => false,
+        };
+        if !use_shm {
+            info!("MIT-SHM not available; using PutImage");
+        }
+        let msb_first = conn.get_setup().image_byte_order() == xcb::IMAGE_ORDER_MSB_FIRST as u8;
+
+        Self {
+            x_handle,
+            window_id,
+            gc,
+            depth,
+            msb_first,
+            buffer: PixelBuffer::new(0, 0),
+            use_shm,
+            shm: None,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// The buffer to draw the next frame into, sized to `size`.
+    pub fn buffer_mut(&mut self, size: (u32, u32)) -> &mut PixelBuffer {
+        self.buffer.resize(size.0, size.1);
+        &mut self.buffer
+    }
+
+    /// Copy the buffer to the window.
+    pub fn present(&mut self) {
+        let (width, height) = (self.buffer.width(), self.buffer.height());
+        if width == 0 || height == 0 {
+            return;
+        }
+        let size = width as usize * height as usize * 4;
+
+        if self.use_shm && self.shm.as_ref().is_none_or(|shm| shm.size < size) {
+            self.shm = None;
+            self.shm = ShmSegment::new(self.x_handle.clone(), size);
+            if self.shm.is_none() {
+                warn!("Could not set up shared memory; falling back to PutImage");
+                self.use_shm = false;
+            }
+        }
+
+        match self.shm.as_mut() {
+            Some(shm) => {
+                convert_to_x(self.buffer.pixels(), &mut shm.bytes_mut()[..size], self.msb_first);
+                xcb::shm::put_image(
+                    self.x_handle.conn_ref(),
+                    self.window_id,
+                    self.gc,
+                    width as u16,
+                    height as u16,
+                    0,
+                    0,
+                    width as u16,
+                    height as u16,
+                    0,
+                    0,
+                    self.depth,
+                    xcb::IMAGE_FORMAT_Z_PIXMAP as u8,
+                    0,
+                    shm.seg,
+                    0,
+                );
+                // The server reads the segment whenever it gets to the request. Wait for it, so
+                // we don't start writing the next frame over this one.
+                let _ = xcb::get_input_focus(self.x_handle.conn_ref()).get_reply();
+            }
+            None => {
+                self.scratch.resize(size, 0);
+                convert_to_x(self.buffer.pixels(), &mut self.scratch, self.msb_first);
+                self.put_image_in_strips(width);
+                self.x_handle.flush();
+            }
+        }
+    }
+
+    /// PutImage, split up so no request goes over the server's maximum request length.
+    fn put_image_in_strips(&self, width: u32) {
+        let conn = self.x_handle.conn_ref();
+        let stride = width as usize * 4;
+        // The maximum is in 4 byte units, and includes the request's own header.
+        let max_bytes = conn.get_maximum_request_length() as usize * 4 - 64;
+        let rows_per_request = (max_bytes / stride).max(1);
+
+        for (strip, rows) in self.scratch.chunks(rows_per_request * stride).enumerate() {
+            xcb::put_image(
+                conn,
+                xcb::IMAGE_FORMAT_Z_PIXMAP as u8,
+                self.window_id,
+                self.gc,
+                width as u16,
+                (rows.len() / stride) as u16,
+                0,
+                (strip * rows_per_request) as i16,
+                0,
+                self.depth,
+                rows,
+            );
+        }
+    }
+}
+
+impl Drop for SoftwareRenderer {
+    fn drop(&mut self) {
+        self.shm = None;
+        xcb::free_gc(self.x_handle.conn_ref(), self.gc);
+        self.x_handle.flush();
+    }
+}
+
+/// Convert RGBA to the server's 32 bit TrueColor layout: 0xffRRGGBB in the server's byte order.
+/// The window is opaque, so the top byte is 0xff in case it's a 32 bit (ARGB) visual.
+fn convert_to_x(rgba: &[u8], out: &mut [u8], msb_first: bool) {
+    for (from, to) in rgba.chunks_exact(4).zip(out.chunks_exact_mut(4)) {
+        if msb_first {
+            to.copy_from_slice(&[0xff, from[0], from[1], from[2]]);
+        } else {
+            to.copy_from_slice(&[from[2], from[1], from[0], 0xff]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba_to_x_pixels() {
+        let rgba = [0x11, 0x22, 0x33, 0x44, 0xaa, 0xbb, 0xcc, 0xdd];
+        let mut out = [0u8; 8];
+        convert_to_x(&rgba, &mut out, false);
+        assert_eq!(out, [0x33, 0x22, 0x11, 0xff, 0xcc, 0xbb, 0xaa, 0xff]);
+        convert_to_x(&rgba, &mut out, true);
+        assert_eq!(out, [0xff, 0x11, 0x22, 0x33, 0xff, 0xaa, 0xbb, 0xcc]);
+    }
+}
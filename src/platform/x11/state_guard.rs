@@ -0,0 +1,128 @@
+// Keeps a panicking `GuiState` from taking the GUI thread (and with it, the host) down.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use log::*;
+
+use crate::gui_state::GuiState;
+use crate::parameters::ParameterChange;
+use super::proxy::Shared;
+
+/// A `GuiState` whose callbacks all go through `call()`. After the first panic it's poisoned, and
+/// never called again; the window stays up, but does nothing.
+pub struct GuardedState {
+    state: Box<dyn GuiState>,
+    shared: Arc<Shared>,
+    poisoned: bool,
+}
+
+impl GuardedState {
+    pub fn new(state: Box<dyn GuiState>, shared: Arc<Shared>) -> Self {
+        Self {
+            state,
+            shared,
+            poisoned: false,
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Call `f` with the state, unless it's poisoned. Returns `None` if it is, or if `f`
+    /// panicked (which poisons it).
+    pub fn call<R>(&mut self, f: impl FnOnce(&mut dyn GuiState) -> R) -> Option<R> {
+        if self.poisoned {
+            return None;
+        }
+        let state = self.state.as_mut();
+        // Unwind safety doesn't matter: nobody looks at the state again after a panic.
+        match panic::catch_unwind(AssertUnwindSafe(|| f(state))) {
+            Ok(result) => Some(result),
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                error!("The GuiState panicked ({}); it won't be called again", message);
+                self.poisoned = true;
+                self.shared.set_panic_message(message);
+                None
+            }
+        }
+    }
+
+    /// Deliver what came in through the proxies and the `ParameterQueue`: messages, then due
+    /// timers, then parameter changes. Returns whether any parameter changed, which calls for a
+    /// redraw.
+    pub fn dispatch_pending(&mut self) -> bool {
+        let shared = self.shared.clone();
+        for message in shared.take_messages() {
+            self.call(|state| state.handle_message(message));
+        }
+
+        for id in shared.take_expired_timers() {
+            self.call(|state| state.timer(id));
+        }
+
+        let mut changed = false;
+        if let Some(parameters) = shared.parameters() {
+            parameters.drain(|change| {
+                changed = true;
+                self.call(|state| match change {
+                    ParameterChange::Parameter { index, value } => {
+                        state.parameter_changed(index, value)
+                    }
+                    ParameterChange::Meter { index, level } => state.meter_changed(index, level),
+                });
+            });
+        }
+        changed
+    }
+
+    /// The state, poisoned or not.
+    pub fn into_inner(self) -> Box<dyn GuiState> {
+        self.state
+    }
+}
+
+/// The message `panic!()` was given, if it was a string.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gui_state::{Frame, Modifiers, MouseEvent};
+    use super::super::waker::Waker;
+
+    struct PanickingState;
+
+    impl GuiState for PanickingState {
+        fn draw(&mut self, _gl: &glow::Context, _frame: &Frame) {}
+        fn handle_mouse(&mut self, _: MouseEvent, x: i32, _: i32, _: Modifiers, _: u32) {
+            panic!("bad click at {}", x);
+        }
+    }
+
+    #[test]
+    fn panics_poison_the_state() {
+        let shared = Arc::new(Shared::new(Arc::new(Waker::new().unwrap()), None, None));
+        let mut state = GuardedState::new(Box::new(PanickingState), shared.clone());
+
+        assert_eq!(state.call(|_| 1), Some(1));
+        let click = |state: &mut dyn GuiState| {
+            state.handle_mouse(MouseEvent::Motion, 3, 4, Modifiers::default(), 0)
+        };
+        assert_eq!(state.call(click), None);
+        assert!(state.is_poisoned());
+        assert_eq!(shared.panic_message(), Some("bad click at 3".to_string()));
+        assert_eq!(state.call(|_| 1), None);
+    }
+}
@@ -0,0 +1,566 @@
+// A `WindowImpl` without a display, for unit-testing `GuiState`s. See `crate::testing`.
+
+use std::ffi::c_void;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::error::WindowError;
+use crate::gui_state::{Frame, GuiState, KeyEvent, Modifiers, MouseEvent};
+use crate::pixel_buffer::PixelBuffer;
+use crate::window::{Backend, RawWindow, WindowImpl, WindowOptions};
+use super::clock::Clock;
+use super::proxy::{PlatformProxy, Shared};
+use super::waker::Waker;
+use super::window_core::WindowCore;
+#[cfg(test)]
+use super::window_core::PANIC_SCREEN_COLOR;
+
+/// The window id every `MockWindow` reports.
+const MOCK_WINDOW_ID: u32 = 1;
+
+/// One call to the `GuiState`'s draw method, as recorded by `MockWindow`.
+#[derive(Debug, Clone, Copy)]
+pub struct DrawCall {
+    /// The timing the state got, on the mock's clock.
+    pub frame: Frame,
+    /// The window's size, in pixels.
+    pub size: (u32, u32),
+}
+
+/// A window that only exists in memory. It owns the `GuiState` and runs the same event handling
+/// as a real window (the same code, minus X: input, resizes, scale changes, messages, timers,
+/// parameters, redraw requests and policies), but only when told to: through `pump_events()`, or
+/// `advance()` on its clock, which only moves when advanced.
+///
+/// There's no GL. `Backend::Software` and `Backend::Auto` draw through
+/// `GuiState::draw_software()`, as if creating the GL context had failed; `Backend::None` calls
+/// `draw_raw()` with null handles. GL backends, the default included, only record the
+/// `DrawCall`s: `GuiState::draw()`, `gl_init()` and `gl_destroy()` are never called.
+pub struct MockWindow {
+    // `None` once closed.
+    core: Option<WindowCore>,
+    shared: Arc<Shared>,
+    clock: Clock,
+    start: Instant,
+    backend: Backend,
+    // Where `resize()` and friends asked the window to go, in pixels. The next `pump_events()`
+    // applies it, like the ConfigureNotify a real window gets.
+    pending_size: Mutex<Option<(u32, u32)>>,
+    visible: AtomicBool,
+    buffer: PixelBuffer,
+    draws: Vec<DrawCall>,
+}
+
+impl MockWindow {
+    /// The current time on the window's clock.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Move the window's clock forward by `by`, then handle whatever is due, like the event loop
+    /// waking up.
+    pub fn advance(&mut self, by: Duration) {
+        self.clock.advance(by);
+        self.pump_events();
+    }
+
+    /// Press or release X mouse button `button` (1 is left, 2 middle, 3 right, 4 to 7 the scroll
+    /// wheel) at (`x`, `y`), in pixels from the window's top-left corner. Like a real window, the
+    /// `GuiState` gets logical units if `WindowOptions::logical_units` is set.
+    pub fn button(&mut self, button: u8, pressed: bool, x: i32, y: i32, modifiers: Modifiers) {
+        let time = self.event_time();
+        if let Some(core) = &mut self.core {
+            core.button(button, pressed, x, y, modifiers, time);
+        }
+    }
+
+    /// Send `MouseEvent::Motion`, `Enter` or `Leave` at (`x`, `y`), in pixels. Clicks and
+    /// scrolling go through `button()`.
+    pub fn pointer(&mut self, mouse_event: MouseEvent, x: i32, y: i32, modifiers: Modifiers) {
+        let time = self.event_time();
+        if let Some(core) = &mut self.core {
+            core.pointer(mouse_event, x, y, modifiers, time);
+        }
+    }
+
+    /// Send a key event, the way the host's key events reach a real window.
+    pub fn key(&mut self, key_event: KeyEvent) {
+        if let Some(core) = &mut self.core {
+            core.key(key_event);
+        }
+    }
+
+    /// Pretend the window moved to a monitor with scale factor `scale`. With logical units, the
+    /// window keeps its logical size, which the host is asked for; the next `pump_events()`
+    /// applies it.
+    pub fn set_scale_factor(&mut self, scale: f64) {
+        let size = self.core.as_mut().and_then(|core| core.scale_changed(scale));
+        if let Some(size) = size {
+            *self.pending_size.lock().unwrap() = Some(size);
+        }
+    }
+
+    /// Pretend the window manager asked to close the window.
+    pub fn request_close(&mut self) {
+        if let Some(core) = &mut self.core {
+            core.close_requested();
+        }
+    }
+
+    /// Whether the window is shown; see `show()` and `hide()`.
+    pub fn is_visible(&self) -> bool {
+        self.visible.load(Ordering::Acquire)
+    }
+
+    /// Every draw so far, oldest first.
+    pub fn draws(&self) -> &[DrawCall] {
+        &self.draws
+    }
+
+    /// Take the draws recorded so far, so the next check starts from scratch.
+    pub fn take_draws(&mut self) -> Vec<DrawCall> {
+        std::mem::take(&mut self.draws)
+    }
+
+    /// What the last software draw left in the buffer.
+    pub fn buffer(&self) -> &PixelBuffer {
+        &self.buffer
+    }
+
+    /// Milliseconds since the window was created, standing in for the X server's timestamps.
+    fn event_time(&self) -> u32 {
+        self.now().duration_since(self.start).as_millis() as u32
+    }
+
+    fn raw_window(&self) -> RawWindow {
+        RawWindow {
+            xcb_connection: std::ptr::null_mut(),
+            xlib_display: std::ptr::null_mut(),
+            screen: 0,
+            window: MOCK_WINDOW_ID,
+            visual_id: 0,
+        }
+    }
+
+    fn draw(&mut self) {
+        let raw_window = self.raw_window();
+        let core = match &mut self.core {
+            Some(core) => core,
+            None => return,
+        };
+        let frame = match core.start_frame() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let size = core.size();
+        match self.backend {
+            Backend::None => core.draw_raw(&raw_window, &frame),
+            Backend::Software => {
+                self.buffer.resize(size.0, size.1);
+                core.draw_software(&mut self.buffer, &frame);
+            }
+            // Nothing to draw with.
+            _ => {}
+        }
+        self.draws.push(DrawCall { frame, size });
+    }
+}
+
+impl WindowImpl for MockWindow {
+    fn new(
+        state: Box<dyn GuiState>,
+        _parent: *mut c_void,
+        size: (u32, u32),
+        options: WindowOptions,
+    ) -> Result<Self, WindowError> {
+        let backend = match options.backend {
+            Backend::Auto => Backend::Software,
+            backend => backend,
+        };
+        let start = Instant::now();
+        let clock = Clock::manual(start);
+        let shared = Arc::new(
+            Shared::new(
+                Arc::new(Waker::new()?),
+                options.parameters.clone(),
+                options.host_resize.clone(),
+            )
+            .with_clock(clock.clone()),
+        );
+        // The scale factor starts out at 1.0, so logical units are pixels for now.
+        let core = WindowCore::new(state, shared.clone(), size, 1.0, &options);
+
+        let mut window = Self {
+            core: Some(core),
+            shared,
+            clock,
+            start,
+            backend,
+            pending_size: Mutex::new(None),
+            visible: AtomicBool::new(true),
+            buffer: PixelBuffer::new(0, 0),
+            draws: Vec::new(),
+        };
+
+        // The same callbacks as a real window gets while it's created and mapped.
+        let raw_window = window.raw_window();
+        if let Some(core) = &mut window.core {
+            if backend == Backend::None {
+                core.call(|state| state.raw_init(&raw_window));
+            }
+            core.call(|state| state.scale_factor_changed(1.0));
+        }
+        // Mapping the window exposes it.
+        window.shared.request_redraw();
+        Ok(window)
+    }
+
+    fn resize(&self, size: (u32, u32)) {
+        if let Some(core) = &self.core {
+            *self.pending_size.lock().unwrap() = Some(core.to_physical_size(size));
+        }
+    }
+
+    fn request_resize(&self, size: (u32, u32)) -> bool {
+        let physical_size = match &self.core {
+            Some(core) => core.to_physical_size(size),
+            None => return false,
+        };
+        if !self.shared.ask_host_to_resize(physical_size) {
+            info!("Host refused to resize the editor to {:?}", size);
+            return false;
+        }
+        self.resize(size);
+        true
+    }
+
+    fn show(&self) {
+        if !self.visible.swap(true, Ordering::AcqRel) {
+            self.shared.request_redraw();
+        }
+    }
+
+    fn hide(&self) {
+        self.visible.store(false, Ordering::Release);
+    }
+
+    fn invalidate(&self) {
+        self.shared.request_redraw();
+    }
+
+    fn set_timer(&self, id: u64, delay: Duration, repeat: bool) {
+        self.shared.set_timer(id, delay, repeat);
+    }
+
+    fn cancel_timer(&self, id: u64) {
+        self.shared.cancel_timer(id);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        match &self.core {
+            Some(core) => {
+                let size = self.pending_size.lock().unwrap().unwrap_or(core.size());
+                core.to_logical_size(size)
+            }
+            None => (0, 0),
+        }
+    }
+
+    fn window_id(&self) -> u32 {
+        MOCK_WINDOW_ID
+    }
+
+    fn raw_window(&self) -> RawWindow {
+        MockWindow::raw_window(self)
+    }
+
+    fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    fn scale_factor(&self) -> f64 {
+        self.shared.scale_factor()
+    }
+
+    fn swap_interval(&self) -> Option<i32> {
+        None
+    }
+
+    fn panic_message(&self) -> Option<String> {
+        self.shared.panic_message()
+    }
+
+    fn proxy(&self) -> PlatformProxy {
+        PlatformProxy::new(self.shared.clone())
+    }
+
+    fn connection_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn wake_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        let core = self.core.as_ref()?;
+        if self.pending_size.lock().unwrap().is_some() {
+            return Some(Duration::ZERO);
+        }
+        core.wait_timeout(self.is_visible())
+    }
+
+    /// One pass of the event loop: the resize the X server would have reported by now, then
+    /// whatever `WindowContext::handle_pending()` does.
+    fn pump_events(&mut self) {
+        let visible = self.is_visible();
+        let pending_size = self.pending_size.lock().unwrap().take();
+        let core = match &mut self.core {
+            Some(core) => core,
+            None => return,
+        };
+        self.shared.waker().drain();
+
+        if let Some(size) = pending_size {
+            core.resized(size);
+        }
+        if let Some(size) = core.take_resize_request() {
+            // Applied on the next pass, like the ConfigureNotify it would cause.
+            *self.pending_size.lock().unwrap() = Some(size);
+        }
+        if let Some(size) = self.shared.take_deferred_host_resize() {
+            // What the host's `pump_events()` does for a threaded window.
+            if self.shared.ask_host_to_resize(size) {
+                *self.pending_size.lock().unwrap() = Some(size);
+            }
+        }
+        if core.dispatch_pending(visible) {
+            self.draw();
+        }
+    }
+
+    fn handle_host_key(&mut self, key_event: KeyEvent) {
+        self.key(key_event);
+    }
+
+    fn close(&mut self) -> Option<Box<dyn GuiState>> {
+        self.shared.close();
+        let mut core = self.core.take()?;
+        if self.backend == Backend::None {
+            core.call(|state| state.raw_destroy());
+        }
+        Some(core.into_state())
+    }
+}
+
+impl Drop for MockWindow {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use crate::parameters::ParameterQueue;
+    use crate::window::{HostResizeCallback, RedrawPolicy};
+
+    /// Writes down every callback, so the tests can look at them after the state is boxed up.
+    struct RecordingState {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl RecordingState {
+        fn new() -> (Box<Self>, Arc<Mutex<Vec<String>>>) {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            (Box::new(Self { log: log.clone() }), log)
+        }
+
+        fn record(&self, entry: String) {
+            self.log.lock().unwrap().push(entry);
+        }
+    }
+
+    impl GuiState for RecordingState {
+        fn draw(&mut self, _gl: &glow::Context, _frame: &Frame) {}
+
+        fn draw_software(&mut self, buffer: &mut PixelBuffer, _frame: &Frame) {
+            buffer.fill([0, 0, 0xff, 0xff]);
+            self.record(format!("draw {}x{}", buffer.width(), buffer.height()));
+        }
+
+        fn handle_mouse(&mut self, _: MouseEvent, x: i32, y: i32, _: Modifiers, time: u32) {
+            if x < 0 {
+                panic!("clicked outside");
+            }
+            self.record(format!("mouse {},{} at {}", x, y, time));
+        }
+
+        fn resized(&mut self, width: u32, height: u32) {
+            self.record(format!("resized {}x{}", width, height));
+        }
+
+        fn timer(&mut self, id: u64) {
+            self.record(format!("timer {}", id));
+        }
+
+        fn handle_message(&mut self, message: Box<dyn Any + Send>) {
+            self.record(format!("message {}", message.downcast::<&str>().unwrap()));
+        }
+
+        fn parameter_changed(&mut self, index: usize, value: f32) {
+            self.record(format!("parameter {} = {}", index, value));
+        }
+
+        fn close_requested(&mut self) {
+            self.record("close requested".to_string());
+        }
+    }
+
+    fn take(log: &Mutex<Vec<String>>) -> Vec<String> {
+        std::mem::take(&mut *log.lock().unwrap())
+    }
+
+    #[test]
+    fn scripted_events_are_handled_in_order() {
+        let (state, log) = RecordingState::new();
+        let parameters = Arc::new(ParameterQueue::new(2, 0));
+        let options = WindowOptions {
+            parameters: Some(parameters.clone()),
+            backend: Backend::Software,
+            logical_units: true,
+            ..Default::default()
+        };
+        let mut window = MockWindow::new(state, std::ptr::null_mut(), (40, 30), options).unwrap();
+        assert_eq!(window.backend(), Backend::Software);
+        window.pump_events();
+        assert_eq!(take(&log), vec!["draw 40x30"]);
+
+        let proxy = window.proxy();
+        proxy.post_message(Box::new("hello"));
+        window.set_timer(7, Duration::from_millis(100), false);
+        parameters.set_parameter(1, 0.5);
+        window.advance(Duration::from_millis(50));
+        assert_eq!(take(&log), vec!["message hello", "parameter 1 = 0.5", "draw 40x30"]);
+
+        window.advance(Duration::from_millis(50));
+        window.pointer(MouseEvent::Motion, 20, 10, Modifiers::default());
+        assert_eq!(take(&log), vec!["timer 7", "mouse 20,10 at 100"]);
+
+        // Doubling the scale keeps the logical size, so the window doubles in pixels once the
+        // resize goes through.
+        window.set_scale_factor(2.0);
+        window.button(1, true, 20, 10, Modifiers::default());
+        // Scroll wheel releases are dropped, like on a real window.
+        window.button(4, false, 20, 10, Modifiers::default());
+        window.pump_events();
+        assert_eq!(take(&log), vec!["mouse 10,5 at 100", "resized 40x30", "draw 80x60"]);
+        assert_eq!(window.buffer().pixel(79, 59), Some([0, 0, 0xff, 0xff]));
+
+        window.resize((50, 50));
+        assert_eq!(window.size(), (50, 50));
+        window.pump_events();
+        assert_eq!(take(&log), vec!["resized 50x50", "draw 100x100"]);
+        assert_eq!(window.draws().last().map(|draw| draw.size), Some((100, 100)));
+
+        window.request_close();
+        assert_eq!(take(&log), vec!["close requested"]);
+    }
+
+    #[test]
+    fn frames_follow_the_redraw_policy() {
+        let (state, _log) = RecordingState::new();
+        let options = WindowOptions {
+            backend: Backend::Software,
+            redraw_policy: RedrawPolicy::MaxFps(50),
+            ..Default::default()
+        };
+        let mut window = MockWindow::new(state, std::ptr::null_mut(), (10, 10), options).unwrap();
+        for _ in 0..10 {
+            window.advance(Duration::from_millis(10));
+        }
+        let deltas: Vec<_> = window.take_draws().iter().map(|draw| draw.frame.delta).collect();
+        // The first frame is drawn right away, then one every 20ms.
+        assert_eq!(deltas, [0, 20, 20, 20, 20].map(Duration::from_millis));
+        assert_eq!(window.next_timeout(), Some(Duration::from_millis(10)));
+
+        // Hidden windows only draw when asked to.
+        window.hide();
+        window.advance(Duration::from_secs(1));
+        assert!(window.draws().is_empty());
+        assert_eq!(window.next_timeout(), None);
+        window.set_timer(1, Duration::from_millis(30), false);
+        assert_eq!(window.next_timeout(), Some(Duration::from_millis(30)));
+        window.invalidate();
+        window.pump_events();
+        assert_eq!(window.draws().len(), 1);
+    }
+
+    #[test]
+    fn host_resizes_wait_for_the_host() {
+        let (state, log) = RecordingState::new();
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let host_asked = asked.clone();
+        let options = WindowOptions {
+            backend: Backend::Software,
+            host_resize: Some(HostResizeCallback::new(move |width, height| {
+                host_asked.lock().unwrap().push((width, height));
+                width <= 100
+            })),
+            ..Default::default()
+        };
+        let mut window = MockWindow::new(state, std::ptr::null_mut(), (10, 10), options).unwrap();
+        window.pump_events();
+        take(&log);
+
+        let proxy = window.proxy();
+        proxy.request_resize((20, 20));
+        window.pump_events();
+        assert_eq!(*asked.lock().unwrap(), [(20, 20)]);
+        window.pump_events();
+        assert_eq!(take(&log), vec!["resized 20x20", "draw 20x20"]);
+
+        proxy.request_resize((200, 20));
+        window.pump_events();
+        window.pump_events();
+        assert_eq!(*asked.lock().unwrap(), [(20, 20), (200, 20)]);
+        assert!(take(&log).is_empty());
+        assert_eq!(window.size(), (20, 20));
+    }
+
+    #[test]
+    fn gl_windows_only_count_frames() {
+        let (state, log) = RecordingState::new();
+        let options = WindowOptions::default();
+        let mut window = MockWindow::new(state, std::ptr::null_mut(), (10, 10), options).unwrap();
+        assert_eq!(window.backend(), Backend::OpenGl);
+        window.pump_events();
+        window.button(1, true, 5, 5, Modifiers::default());
+        assert_eq!(window.draws().len(), 1);
+        assert_eq!(take(&log), vec!["mouse 5,5 at 0"]);
+    }
+
+    #[test]
+    fn panics_leave_the_panic_screen() {
+        let (state, log) = RecordingState::new();
+        let options = WindowOptions {
+            backend: Backend::Software,
+            panic_screen: true,
+            ..Default::default()
+        };
+        let mut window = MockWindow::new(state, std::ptr::null_mut(), (4, 4), options).unwrap();
+        window.button(1, true, -1, 0, Modifiers::default());
+        assert_eq!(window.panic_message(), Some("clicked outside".to_string()));
+        window.pump_events();
+        assert_eq!(window.draws().len(), 1);
+        assert_eq!(window.buffer().pixel(0, 0), Some(PANIC_SCREEN_COLOR));
+        assert!(take(&log).is_empty());
+        assert!(window.close().is_some());
+        assert!(window.proxy().is_closed());
+    }
+}
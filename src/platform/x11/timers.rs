@@ -0,0 +1,99 @@
+// The timers behind `Window::set_timer()` and friends, fired from the event loop.
+
+use std::time::{Duration, Instant};
+
+/// The shortest interval a repeating timer gets. A zero interval would make it due again as soon
+/// as it fired, and the event loop would spin calling it.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Timer {
+    id: u64,
+    deadline: Instant,
+    // `Some` for repeating timers.
+    interval: Option<Duration>,
+}
+
+#[derive(Default)]
+pub struct Timers {
+    timers: Vec<Timer>,
+}
+
+impl Timers {
+    /// Schedule timer `id` for `now + delay`, replacing any timer with the same id. With
+    /// `repeat`, it keeps firing every `delay` (but at least `MIN_INTERVAL`) after that.
+    pub fn set(&mut self, id: u64, now: Instant, delay: Duration, repeat: bool) {
+        self.cancel(id);
+        self.timers.push(Timer {
+            id,
+            deadline: now + delay,
+            interval: if repeat { Some(delay.max(MIN_INTERVAL)) } else { None },
+        });
+    }
+
+    pub fn cancel(&mut self, id: u64) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    /// When the next timer fires, if there is one.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.iter().map(|timer| timer.deadline).min()
+    }
+
+    /// The ids of the timers due at `now`, earliest first. One-shot timers are removed, and
+    /// repeating ones rescheduled.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<u64> {
+        let mut expired: Vec<(Instant, u64)> = Vec::new();
+        self.timers.retain_mut(|timer| {
+            if timer.deadline > now {
+                return true;
+            }
+            expired.push((timer.deadline, timer.id));
+            match timer.interval {
+                Some(interval) => {
+                    timer.deadline += interval;
+                    // If we fell behind (e.g. the GUI thread was busy), don't try to catch up
+                    // with a burst of calls.
+                    if timer.deadline <= now {
+                        timer.deadline = now + interval;
+                    }
+                    true
+                }
+                None => false,
+            }
+        });
+        expired.sort();
+        expired.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_shot_and_repeating_timers() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut timers = Timers::default();
+        timers.set(1, start, ms(30), false);
+        timers.set(2, start, ms(10), true);
+        assert_eq!(timers.next_deadline(), Some(start + ms(10)));
+
+        assert_eq!(timers.take_expired(start + ms(5)), Vec::<u64>::new());
+        assert_eq!(timers.take_expired(start + ms(10)), vec![2]);
+        assert_eq!(timers.take_expired(start + ms(30)), vec![2, 1]);
+        assert_eq!(timers.next_deadline(), Some(start + ms(40)));
+
+        // Setting an existing id moves it.
+        timers.set(2, start + ms(30), ms(100), false);
+        assert_eq!(timers.next_deadline(), Some(start + ms(130)));
+        timers.cancel(2);
+        assert_eq!(timers.next_deadline(), None);
+
+        // A zero interval fires right away, then no more than once a millisecond.
+        timers.set(3, start, ms(0), true);
+        assert_eq!(timers.take_expired(start), vec![3]);
+        assert_eq!(timers.take_expired(start), Vec::<u64>::new());
+        assert_eq!(timers.next_deadline(), Some(start + ms(1)));
+    }
+}
@@ -0,0 +1,107 @@
+// A self-pipe for waking up the event loop from other threads. The loop waits on the pipe's read
+// end together with the X connection, so writing a byte to it interrupts the wait without having
+// to send anything through the X server.
+
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use log::*;
+
+use crate::error::WindowError;
+
+pub struct Waker {
+    read_fd: RawFd,
+    write_fd: RawFd,
+}
+
+impl Waker {
+    pub fn new() -> Result<Self, WindowError> {
+        let mut fds = [0; 2];
+        // Non-blocking on both ends: a full pipe already means "wake up", and draining it must
+        // never block the event loop.
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            let error = std::io::Error::last_os_error();
+            return Err(WindowError::Os(format!("could not create wake-up pipe: {}", error)));
+        }
+        Ok(Self {
+            read_fd: fds[0],
+            write_fd: fds[1],
+        })
+    }
+
+    /// Wake up the event loop. Safe to call from any thread, any number of times.
+    pub fn wake(&self) {
+        let byte = 1u8;
+        // If the pipe is full, the loop has plenty of wake-ups pending already.
+        unsafe { libc::write(self.write_fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+
+    /// The pipe's read end, which becomes readable when `wake()` is called.
+    pub fn read_fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    /// Empty the pipe, so the next wait blocks again.
+    pub fn drain(&self) {
+        let mut buffer = [0u8; 64];
+        while unsafe {
+            libc::read(self.read_fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+        } > 0
+        {}
+    }
+
+    /// Block until `connection_fd` is readable, `wake()` is called, or `timeout` runs out
+    /// (`None` waits forever).
+    pub fn wait(&self, connection_fd: RawFd, timeout: Option<Duration>) {
+        let mut fds = [
+            libc::pollfd { fd: connection_fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.read_fd, events: libc::POLLIN, revents: 0 },
+        ];
+        let timeout_ms = match timeout {
+            // Round up, so we don't spin on sub-millisecond timeouts.
+            Some(timeout) => timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+        let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+        if result < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                warn!("poll() failed: {}", error);
+            }
+        }
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn wake_interrupts_wait() {
+        let waker = Waker::new().unwrap();
+        // Nothing is ever written to the other pipe; only the waker can end the wait.
+        let idle = Waker::new().unwrap();
+
+        waker.wake();
+        waker.wake();
+        let start = Instant::now();
+        waker.wait(idle.read_fd, Some(Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Once drained, we wait for the full timeout again.
+        waker.drain();
+        let start = Instant::now();
+        waker.wait(idle.read_fd, Some(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,860 @@
+use std::os::raw::c_void;
+use std::ptr::null_mut;
+use std::sync::Arc;
+use std::time::Duration;
+
+use glow::HasContext;
+use x11::{xlib, glx};
+use log::*;
+
+use crate::error::WindowError;
+use crate::gui_state::{GuiState, KeyEvent, MouseEvent};
+use crate::window::{Backend, GlConfig, RawWindow, Vsync, WindowOptions};
+use super::{gl_utils, input, software};
+use super::gl_debug::DebugOutput;
+use super::keyboard::Keyboard;
+use super::proxy::Shared;
+use super::scale::{self, ScaleDetector};
+use super::software::SoftwareRenderer;
+use super::window_core::WindowCore;
+#[cfg(feature = "egl")]
+use super::egl::{self, EglRenderer, EglSetup};
+#[cfg(feature = "egl")]
+use crate::window::EglApi;
+use super::x_handle::XHandle;
+
+/// What draws the window's contents.
+enum Renderer {
+    OpenGl { gl_context: *mut glx::__GLXcontextRec },
+    Software(SoftwareRenderer),
+    #[cfg(feature = "egl")]
+    Egl(Box<EglRenderer>),
+    /// `Backend::None`: the `GuiState` brings its own.
+    Raw,
+}
+
+/// The renderer we picked, before the window exists.
+enum RendererSetup {
+    Glx(GlxSetup),
+    #[cfg(feature = "egl")]
+    Egl(Box<EglSetup>, EglApi),
+    Software,
+    Raw,
+}
+
+/// Everything `setup_glx()` picks for an OpenGL window. Dropping this destroys the context,
+/// unless it was taken.
+struct GlxSetup {
+    x_handle: Arc<XHandle>,
+    visual_id: u32,
+    screen_num: usize,
+    depth: u8,
+    gl_context: *mut glx::__GLXcontextRec,
+}
+
+impl GlxSetup {
+    fn take_context(&mut self) -> *mut glx::__GLXcontextRec {
+        std::mem::replace(&mut self.gl_context, null_mut())
+    }
+}
+
+impl Drop for GlxSetup {
+    fn drop(&mut self) {
+        if !self.gl_context.is_null() {
+            unsafe { glx::glXDestroyContext(self.x_handle.raw_display(), self.gl_context) };
+        }
+    }
+}
+
+/// The X resources and renderer behind a window, destroyed when this is dropped.
+struct Resources {
+    x_handle: Arc<XHandle>,
+    window_id: u32,
+    color_map_id: u32,
+    // Only `None` while being dropped.
+    renderer: Option<Renderer>,
+    // The GL functions for the renderer's context, if it has one.
+    gl: Option<glow::Context>,
+}
+
+impl Resources {
+    /// Make the GL context current, if there is one. Returns whether there is.
+    fn make_gl_current(&self) -> bool {
+        match self.renderer.as_ref() {
+            Some(Renderer::OpenGl { gl_context }) => unsafe {
+                let display = self.x_handle.raw_display();
+                glx::glXMakeCurrent(display, self.window_id as xlib::XID, *gl_context);
+                true
+            },
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(renderer)) => {
+                renderer.make_current();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The address of GL function `name` for the renderer's context, or null.
+    fn get_proc_address(&self, name: &str) -> *const c_void {
+        match self.renderer.as_ref() {
+            Some(Renderer::OpenGl { .. }) => gl_utils::get_proc_address(name),
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(renderer)) => renderer.get_proc_address(name),
+            _ => std::ptr::null(),
+        }
+    }
+
+    /// Apply `vsync` to the GL context, which has to be current. Returns the swap interval in
+    /// effect, if known.
+    fn set_swap_interval(&self, vsync: Vsync) -> Option<i32> {
+        match self.renderer.as_ref() {
+            Some(Renderer::OpenGl { .. }) => unsafe {
+                let window = self.window_id as xlib::XID;
+                gl_utils::set_swap_interval(&self.x_handle, window, vsync)
+            },
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(renderer)) => renderer.set_swap_interval(vsync),
+            _ => None,
+        }
+    }
+
+    fn release_gl_current(&self) {
+        match self.renderer.as_ref() {
+            Some(Renderer::OpenGl { .. }) => unsafe {
+                glx::glXMakeCurrent(self.x_handle.raw_display(), 0, null_mut());
+            },
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(renderer)) => renderer.release_current(),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Resources {
+    fn drop(&mut self) {
+        // Goodbye, cruel world! :(
+        match self.renderer.take() {
+            Some(Renderer::OpenGl { gl_context }) => unsafe {
+                glx::glXDestroyContext(self.x_handle.raw_display(), gl_context);
+            },
+            Some(Renderer::Software(renderer)) => drop(renderer),
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(renderer)) => drop(renderer),
+            Some(Renderer::Raw) | None => {}
+        }
+        xcb::destroy_window(self.x_handle.conn_ref(), self.window_id);
+        xcb::free_colormap(self.x_handle.conn_ref(), self.color_map_id);
+        self.x_handle.flush();
+    }
+}
+
+/// What the `PlatformWindow` keeps of a window whose `WindowContext` may live on another thread.
+#[derive(Debug, Clone, Copy)]
+pub struct WindowInfo {
+    pub window_id: u32,
+    pub visual_id: u32,
+    pub backend: Backend,
+}
+
+/// One window and everything needed to handle its events: the X resources, the GL context and the
+/// `GuiState`. Whoever owns this (the GUI thread, or the host's thread in host-driven mode) is the
+/// one calling into the `GuiState`.
+pub struct WindowContext {
+    x_handle: Arc<XHandle>,
+    window_id: u32,
+    visual_id: u32,
+    parent_id: u32,
+    follow_parent: bool,
+    take_focus: bool,
+    // Set when the size changed; the GL viewport is updated on the next draw.
+    viewport_outdated: bool,
+    // Whether we could be seen; continuous redrawing pauses otherwise.
+    mapped: bool,
+    toplevel_mapped: bool,
+    obscured: bool,
+    // The window that gets moved around when the user moves the host's window. We check the
+    // scale factor again when it moves, in case it moved to another monitor.
+    toplevel_id: u32,
+    root_id: u32,
+    scale_detector: ScaleDetector,
+    resources: Resources,
+    // Declared after `resources`: the GL may call into this until the context is destroyed.
+    gl_debug: Option<Box<DebugOutput>>,
+    protocols_atom: u32,
+    delete_window_atom: u32,
+    keyboard: Option<Keyboard>,
+    core: WindowCore,
+}
+
+impl WindowContext {
+    /// Create the window. The `GuiState` is only taken out of `state` once nothing can fail any
+    /// more, so it's still there if this returns an error.
+    pub fn new(
+        x_handle: Arc<XHandle>,
+        shared: Arc<Shared>,
+        state: &mut Option<Box<dyn GuiState>>,
+        mut parent_id: u32,
+        mut size: (u32, u32),
+        options: &WindowOptions,
+    ) -> Result<Self, WindowError> {
+        // Allow deleting the window via the "protocols" / "delete_window" atoms (??? ...magic)
+        let protocols_atom = x_handle.make_cookie_atom(false, "WM_PROTOCOLS")?;
+        let delete_window_atom = x_handle.make_cookie_atom(false, "WM_DELETE_WINDOW")?;
+
+        // Okay, now the fun part. Make an OpenGL context!
+        // We do this before creating any X resources, so there's nothing to clean up if it fails.
+        // The setup destroys its context if we fail before it's turned into a `Renderer`.
+        let setup = match options.backend {
+            Backend::Software => RendererSetup::Software,
+            Backend::None => RendererSetup::Raw,
+            Backend::OpenGl => RendererSetup::Glx(setup_glx(&x_handle, &options.gl_config)?),
+            Backend::Auto => match setup_glx(&x_handle, &options.gl_config) {
+                Ok(glx) => RendererSetup::Glx(glx),
+                Err(e) => {
+                    warn!("Could not set up OpenGL ({}); falling back to software rendering", e);
+                    RendererSetup::Software
+                }
+            },
+            #[cfg(feature = "egl")]
+            Backend::Egl(api) => {
+                let setup = egl::setup_egl(&x_handle, api, &options.gl_config)?;
+                RendererSetup::Egl(Box::new(setup), api)
+            }
+            #[cfg(not(feature = "egl"))]
+            Backend::Egl(_) => {
+                return Err(WindowError::Egl("built without the `egl` feature".to_string()));
+            }
+        };
+
+        let (visual_id, screen_num, depth) = match &setup {
+            RendererSetup::Glx(glx) => (glx.visual_id, glx.screen_num, glx.depth),
+            #[cfg(feature = "egl")]
+            RendererSetup::Egl(egl, _) => {
+                (egl.visual_id, x_handle.screen_num() as usize, egl.depth)
+            }
+            // The software backend just uses the screen's default visual.
+            RendererSetup::Software => {
+                let screen_num = x_handle.screen_num() as usize;
+                let screen = x_handle.screen(screen_num);
+                let depth = screen.root_depth();
+                if !software::supports_depth(depth) {
+                    return Err(WindowError::UnsupportedVisual(depth));
+                }
+                (screen.root_visual(), screen_num, depth)
+            }
+            RendererSetup::Raw => {
+                let screen_num = x_handle.screen_num() as usize;
+                let screen = x_handle.screen(screen_num);
+                (screen.root_visual(), screen_num, screen.root_depth())
+            }
+        };
+
+        // Get the screen struct from the visual info for creating the colormap and window
+        let screen = x_handle.screen(screen_num);
+        let embedded = parent_id != 0;
+        let root_id = screen.root();
+        if !embedded {
+            parent_id = root_id;
+        }
+
+        // We need the scale factor up front if `size` is in logical units. The window doesn't
+        // exist yet, so go by where the parent is.
+        let scale_detector = ScaleDetector::new(&x_handle, root_id);
+        let (parent_x, parent_y) = root_position(&x_handle, parent_id, root_id, 0, 0);
+        let scale = scale_detector.scale_at(parent_x, parent_y);
+        shared.set_scale_factor(scale);
+        if options.logical_units {
+            size = scale::to_physical(size, scale);
+        }
+
+        // Create a color map
+        let color_map_id = x_handle.generate_id();
+        let color_map_cookie = xcb::create_colormap_checked(
+            x_handle.conn_ref(),
+            xcb::COLORMAP_ALLOC_NONE as u8,
+            color_map_id,
+            parent_id,
+            visual_id,
+        );
+
+        // Create the actual window
+        #[rustfmt::skip]
+        let window_options = &[
+            (xcb::CW_BACK_PIXEL, screen.white_pixel()),
+            (xcb::CW_BORDER_PIXEL, screen.black_pixel()),
+            (xcb::CW_EVENT_MASK, xcb::EVENT_MASK_EXPOSURE
+                | xcb::EVENT_MASK_BUTTON_PRESS | xcb::EVENT_MASK_BUTTON_RELEASE
+                | xcb::EVENT_MASK_POINTER_MOTION
+                | xcb::EVENT_MASK_ENTER_WINDOW | xcb::EVENT_MASK_LEAVE_WINDOW
+                | xcb::EVENT_MASK_KEY_PRESS | xcb::EVENT_MASK_KEY_RELEASE
+                | xcb::EVENT_MASK_FOCUS_CHANGE
+                | xcb::EVENT_MASK_STRUCTURE_NOTIFY
+                | xcb::EVENT_MASK_VISIBILITY_CHANGE),
+            (xcb::CW_COLORMAP, color_map_id)
+        ];
+        let window_id = x_handle.generate_id();
+        let window_cookie = xcb::create_window_checked(
+            x_handle.conn_ref(),
+            depth,
+            window_id,
+            parent_id,
+            0,
+            0,
+            size.0 as u16,
+            size.1 as u16,
+            0,
+            xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+            visual_id,
+            window_options,
+        );
+
+        // From here on, the window and colormap are cleaned up by Resources if anything fails.
+        let mut resources = Resources {
+            x_handle: x_handle.clone(),
+            window_id,
+            color_map_id,
+            renderer: None,
+            gl: None,
+        };
+        x_handle.check(color_map_cookie, "CreateColormap")?;
+        x_handle.check(window_cookie, "CreateWindow")?;
+        resources.renderer = Some(match setup {
+            RendererSetup::Glx(mut glx) => Renderer::OpenGl { gl_context: glx.take_context() },
+            #[cfg(feature = "egl")]
+            RendererSetup::Egl(setup, api) => {
+                Renderer::Egl(Box::new(EglRenderer::new(*setup, window_id, api)?))
+            }
+            RendererSetup::Software => {
+                Renderer::Software(SoftwareRenderer::new(x_handle.clone(), window_id, depth))
+            }
+            RendererSetup::Raw => Renderer::Raw,
+        });
+
+        // Ask the window manager to send WM_DELETE_WINDOW instead of killing our connection when
+        // the user closes the window.
+        let protocols = [delete_window_atom];
+        let protocols_cookie = xcb::change_property_checked(
+            x_handle.conn_ref(),
+            xcb::PROP_MODE_REPLACE as u8,
+            window_id,
+            protocols_atom,
+            xcb::ATOM_ATOM,
+            32,
+            &protocols,
+        );
+
+        // Watch the parent's size too, so we can follow it. Event masks are per client, so this
+        // doesn't interfere with the host's own event handling.
+        if embedded && options.follow_parent {
+            watch_structure(&x_handle, parent_id);
+        }
+
+        // Map (display) the window.
+        let map_cookie = xcb::map_window_checked(x_handle.conn_ref(), window_id);
+        x_handle.check(protocols_cookie, "ChangeProperty")?;
+        x_handle.check(map_cookie, "MapWindow")?;
+
+        // Nothing fails after this.
+        let state = state.take().expect("WindowContext::new() needs a GuiState");
+        let mut core = WindowCore::new(state, shared.clone(), size, scale, options);
+
+        let mut gl_debug = None;
+        if resources.make_gl_current() {
+            // Each window loads the functions for its own context; nothing global is touched, so
+            // other windows can keep drawing meanwhile.
+            let get_proc_address = |name: &str| resources.get_proc_address(name);
+            let gl = unsafe { glow::Context::from_loader_function(get_proc_address) };
+            let info = unsafe { gl_utils::query_gl_info(&gl) };
+            info!("OpenGL {} on {} ({})", info.version_string, info.renderer, info.vendor);
+            if options.gl_config.debug {
+                let debug_break = options.gl_config.debug_break;
+                gl_debug =
+                    unsafe { DebugOutput::install(&gl, &info, get_proc_address, debug_break) };
+            }
+            let swap_interval = resources.set_swap_interval(options.gl_config.vsync);
+            info!("Swap interval: {:?}", swap_interval);
+            shared.set_swap_interval(swap_interval);
+
+            core.call(|state| state.gl_init(&gl, &info));
+            unsafe { gl_utils::check_gl_error(&gl) };
+            resources.release_gl_current();
+            resources.gl = Some(gl);
+            if let Some(gl_debug) = &gl_debug {
+                core.call(|_| gl_debug.check());
+            }
+        }
+
+        let keyboard = Keyboard::new(&x_handle);
+
+        let toplevel_id = find_toplevel(&x_handle, window_id, root_id);
+        if toplevel_id != window_id {
+            watch_structure(&x_handle, toplevel_id);
+        }
+
+        let mut context = Self {
+            resources,
+            gl_debug,
+            x_handle,
+            window_id,
+            visual_id,
+            parent_id,
+            follow_parent: embedded && options.follow_parent,
+            take_focus: options.take_focus,
+            viewport_outdated: false,
+            mapped: false,
+            toplevel_mapped: true,
+            obscured: false,
+            toplevel_id,
+            root_id,
+            scale_detector,
+            protocols_atom,
+            delete_window_atom,
+            keyboard,
+            core,
+        };
+
+        if let Some(Renderer::Raw) = context.resources.renderer {
+            let raw_window = context.raw_window();
+            context.core.call(|state| state.raw_init(&raw_window));
+        }
+
+        // The parent may already have a different size than we were asked for.
+        if context.follow_parent {
+            let cookie = xcb::get_geometry(context.x_handle.conn_ref(), parent_id);
+            if let Ok(geometry) = cookie.get_reply() {
+                let parent_size = (geometry.width() as u32, geometry.height() as u32);
+                if parent_size != size {
+                    context.configure(parent_size);
+                }
+            }
+        }
+
+        context.core.call(|state| state.scale_factor_changed(scale));
+
+        Ok(context)
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.window_id
+    }
+
+    pub fn info(&self) -> WindowInfo {
+        WindowInfo {
+            window_id: self.window_id,
+            visual_id: self.visual_id,
+            backend: self.backend(),
+        }
+    }
+
+    fn raw_window(&self) -> RawWindow {
+        self.x_handle.raw_window(self.window_id, self.visual_id)
+    }
+
+    /// Whether the window is being closed. The event loop leaves it alone until it's gone.
+    pub fn is_closed(&self) -> bool {
+        self.core.shared().is_closed()
+    }
+
+    /// Whether events on `window` are for this context: its own, its top-level's, and the
+    /// parent's when following it.
+    pub fn watches(&self, window: u32) -> bool {
+        window == self.window_id
+            || window == self.toplevel_id
+            || (self.follow_parent && window == self.parent_id)
+    }
+
+    /// Pass on a key event the host sent us.
+    pub fn handle_host_key(&mut self, key_event: KeyEvent) {
+        self.core.key(key_event);
+    }
+
+    /// Destroy the window and hand back its `GuiState`.
+    pub fn into_state(mut self) -> Box<dyn GuiState> {
+        if let Some(gl) = &self.resources.gl {
+            self.resources.make_gl_current();
+            self.core.call(|state| state.gl_destroy(gl));
+            self.resources.release_gl_current();
+        }
+        if let Some(Renderer::Raw) = self.resources.renderer {
+            self.core.call(|state| state.raw_destroy());
+        }
+        self.core.into_state()
+    }
+
+    pub fn handle_event(&mut self, ev: &xcb::GenericEvent) {
+        if self.scale_detector.handle_event(&self.x_handle, ev) {
+            self.update_scale();
+            return;
+        }
+
+        let ev_type = ev.response_type() & !0x80;
+        // We only watch other windows (the parent, the top-level) for their structure. Anything
+        // else on them was selected by another window sharing the connection.
+        let structure_event = matches!(
+            ev_type,
+            xcb::CONFIGURE_NOTIFY | xcb::MAP_NOTIFY | xcb::UNMAP_NOTIFY
+        );
+        let foreign = matches!(event_window(ev), Some(window) if window != self.window_id);
+        if foreign && !structure_event {
+            return;
+        }
+        match ev_type {
+            xcb::EXPOSE => {
+                // X11's draw event.
+                self.draw();
+            }
+            xcb::BUTTON_PRESS | xcb::BUTTON_RELEASE => {
+                // X11's mouse click (down/up) events. Both have the same layout.
+                let button_event =
+                    unsafe { xcb::cast_event::<xcb::ButtonPressEvent>(ev) };
+
+                let (x, y) = (button_event.event_x() as i32, button_event.event_y() as i32);
+                let modifiers = input::modifiers_from_state(button_event.state());
+                let pressed = ev_type == xcb::BUTTON_PRESS;
+
+                // We're (usually) embedded in the host's window, so nobody will give us
+                // keyboard focus unless we take it.
+                if pressed && self.take_focus {
+                    xcb::set_input_focus(
+                        self.x_handle.conn_ref(),
+                        xcb::INPUT_FOCUS_PARENT as u8,
+                        self.window_id,
+                        button_event.time(),
+                    );
+                    self.x_handle.flush();
+                }
+
+                let time = button_event.time();
+                self.core.button(button_event.detail(), pressed, x, y, modifiers, time);
+            }
+            xcb::MOTION_NOTIFY => {
+                let motion_event =
+                    unsafe { xcb::cast_event::<xcb::MotionNotifyEvent>(ev) };
+                let (x, y) = (motion_event.event_x() as i32, motion_event.event_y() as i32);
+                let modifiers = input::modifiers_from_state(motion_event.state());
+                let time = motion_event.time();
+                self.core.pointer(MouseEvent::Motion, x, y, modifiers, time);
+            }
+            xcb::ENTER_NOTIFY | xcb::LEAVE_NOTIFY => {
+                // Enter and leave events have the same layout, too.
+                let crossing_event =
+                    unsafe { xcb::cast_event::<xcb::EnterNotifyEvent>(ev) };
+                let mouse_event = if ev_type == xcb::ENTER_NOTIFY {
+                    MouseEvent::Enter
+                } else {
+                    MouseEvent::Leave
+                };
+                let (x, y) = (crossing_event.event_x() as i32, crossing_event.event_y() as i32);
+                let modifiers = input::modifiers_from_state(crossing_event.state());
+                let time = crossing_event.time();
+                self.core.pointer(mouse_event, x, y, modifiers, time);
+            }
+            xcb::KEY_PRESS | xcb::KEY_RELEASE => {
+                // Key press and release events have the same layout.
+                let key_event = unsafe { xcb::cast_event::<xcb::KeyPressEvent>(ev) };
+                if let Some(keyboard) = self.keyboard.as_mut() {
+                    let key_event = keyboard.key_event(
+                        key_event.detail(),
+                        key_event.state(),
+                        ev_type == xcb::KEY_PRESS,
+                        key_event.time(),
+                    );
+                    self.core.key(key_event);
+                }
+            }
+            xcb::FOCUS_OUT => {
+                // We won't see the releases for keys that are held down right now.
+                if let Some(keyboard) = self.keyboard.as_mut() {
+                    keyboard.reset();
+                }
+            }
+            xcb::CONFIGURE_NOTIFY => {
+                let configure_event =
+                    unsafe { xcb::cast_event::<xcb::ConfigureNotifyEvent>(ev) };
+                let size = (configure_event.width() as u32, configure_event.height() as u32);
+                let window = configure_event.window();
+                let following = window == self.parent_id && self.follow_parent;
+                if window == self.window_id {
+                    if self.core.resized(size) {
+                        self.viewport_outdated = true;
+                    }
+                } else if following && size != self.core.size() {
+                    self.configure(size);
+                }
+                if window == self.window_id || window == self.toplevel_id {
+                    // We (or the host's window) may have moved to another monitor.
+                    self.update_scale();
+                }
+            }
+            xcb::MAP_NOTIFY | xcb::UNMAP_NOTIFY => {
+                // Both have the window at the same offset.
+                let map_event = unsafe { xcb::cast_event::<xcb::MapNotifyEvent>(ev) };
+                let mapped = ev_type == xcb::MAP_NOTIFY;
+                if map_event.window() == self.window_id {
+                    self.mapped = mapped;
+                } else if map_event.window() == self.toplevel_id {
+                    self.toplevel_mapped = mapped;
+                }
+            }
+            xcb::VISIBILITY_NOTIFY => {
+                let visibility_event =
+                    unsafe { xcb::cast_event::<xcb::VisibilityNotifyEvent>(ev) };
+                self.obscured = visibility_event.state() == xcb::VISIBILITY_FULLY_OBSCURED as u8;
+            }
+            xcb::MAPPING_NOTIFY => {
+                if let Some(keyboard) = self.keyboard.as_mut() {
+                    keyboard.reload_keymap(&self.x_handle);
+                }
+            }
+            xcb::CLIENT_MESSAGE => {
+                info!("client_message");
+                let client_message_event =
+                    unsafe { xcb::cast_event::<xcb::ClientMessageEvent>(ev) };
+                if client_message_event.type_() == self.protocols_atom
+                    && client_message_event.format() == 32
+                    {
+                        let protocol = client_message_event.data().data32()[0];
+                        if protocol == self.delete_window_atom {
+                            // The user closed the window; what that means is up to the state.
+                            // We only stop when the Window is dropped.
+                            info!("delete_window message received");
+                            self.core.close_requested();
+                            return;
+                        }
+                    }
+                info!("Uhh.. Some other client_message I guess.");
+            }
+            _ => {
+                info!("some other event");
+            }
+        }
+    }
+
+    /// Resize the window. We find out it worked through the CONFIGURE_NOTIFY that follows.
+    fn configure(&self, size: (u32, u32)) {
+        xcb::configure_window(
+            self.x_handle.conn_ref(),
+            self.window_id,
+            &[
+                (xcb::CONFIG_WINDOW_WIDTH as u16, size.0),
+                (xcb::CONFIG_WINDOW_HEIGHT as u16, size.1),
+            ],
+        );
+        self.x_handle.flush();
+    }
+
+    /// Check whether the scale factor changed (e.g. we moved to another monitor) and tell the
+    /// `GuiState` if it did.
+    fn update_scale(&mut self) {
+        let size = self.core.size();
+        let (x, y) = root_position(
+            &self.x_handle,
+            self.window_id,
+            self.root_id,
+            (size.0 / 2) as i16,
+            (size.1 / 2) as i16,
+        );
+        let scale = self.scale_detector.scale_at(x, y);
+        if let Some(size) = self.core.scale_changed(scale) {
+            self.configure(size);
+        }
+    }
+
+    /// Deliver the messages posted through `WindowProxy`s and the changes from the
+    /// `ParameterQueue`, then redraw if anything changed or anyone asked for it.
+    pub fn handle_pending(&mut self) {
+        if let Some(size) = self.core.take_resize_request() {
+            self.configure(size);
+        }
+        if self.core.dispatch_pending(self.is_visible()) {
+            self.draw();
+        }
+    }
+
+    /// Whether any of the window could be on screen.
+    fn is_visible(&self) -> bool {
+        self.mapped && self.toplevel_mapped && !self.obscured
+    }
+
+    /// How long the event loop may block waiting for events. `None` means forever.
+    pub fn wait_timeout(&self) -> Option<Duration> {
+        self.core.wait_timeout(self.is_visible())
+    }
+
+    /// Which backend this window ended up with (never `Backend::Auto`).
+    pub fn backend(&self) -> Backend {
+        match self.resources.renderer {
+            Some(Renderer::Software(_)) => Backend::Software,
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(ref renderer)) => Backend::Egl(renderer.api()),
+            Some(Renderer::Raw) => Backend::None,
+            _ => Backend::OpenGl,
+        }
+    }
+
+    fn draw(&mut self) {
+        // A poisoned state has nothing to draw; show that something went wrong, if wanted.
+        let frame = match self.core.start_frame() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let size = self.core.size();
+        match self.resources.renderer.as_mut() {
+            Some(Renderer::OpenGl { .. }) => {
+                let gl = self.resources.gl.as_ref().unwrap();
+                self.resources.make_gl_current();
+                if self.viewport_outdated {
+                    unsafe { gl.viewport(0, 0, size.0 as i32, size.1 as i32) };
+                    self.viewport_outdated = false;
+                }
+                self.core.draw_gl(gl, &frame);
+                unsafe {
+                    gl_utils::check_gl_error(gl);
+                    glx::glXSwapBuffers(self.x_handle.raw_display(), self.window_id as xlib::XID);
+                }
+                self.resources.release_gl_current();
+            }
+            Some(Renderer::Software(renderer)) => {
+                self.core.draw_software(renderer.buffer_mut(size), &frame);
+                renderer.present();
+            }
+            #[cfg(feature = "egl")]
+            Some(Renderer::Egl(renderer)) => {
+                let gl = self.resources.gl.as_ref().unwrap();
+                renderer.make_current();
+                if self.viewport_outdated {
+                    unsafe { gl.viewport(0, 0, size.0 as i32, size.1 as i32) };
+                    self.viewport_outdated = false;
+                }
+                self.core.draw_gl(gl, &frame);
+                unsafe { gl_utils::check_gl_error(gl) };
+                renderer.swap_buffers();
+                renderer.release_current();
+            }
+            Some(Renderer::Raw) => {
+                let raw_window = self.raw_window();
+                self.core.draw_raw(&raw_window, &frame);
+            }
+            None => {}
+        }
+        if let Some(gl_debug) = &self.gl_debug {
+            self.core.call(|_| gl_debug.check());
+        }
+    }
+}
+
+/// Pick an fbconfig and visual for an OpenGL window, and create the GL context.
+fn setup_glx(x_handle: &Arc<XHandle>, config: &GlConfig) -> Result<GlxSetup, WindowError> {
+    if config.srgb {
+        // Without GLX, say so rather than blaming a missing extension.
+        gl_utils::glx_dec_version(x_handle.raw_display())?;
+        let glx_exts = gl_utils::glx_extensions(x_handle);
+        if !glx_exts.split(' ').any(|ext| ext.ends_with("_framebuffer_sRGB")) {
+            return Err(WindowError::MissingGlxExtension("GLX_ARB_framebuffer_sRGB"));
+        }
+    }
+
+    // Create visual info for the window
+    let visual_info_options = gl_utils::fbconfig_attributes(config);
+    let glx_frame_buffer_config = gl_utils::get_glxfbconfig(
+        x_handle.raw_display(),
+        x_handle.screen_num(),
+        &visual_info_options,
+    )?;
+    let visual_info = unsafe {
+        glx::glXGetVisualFromFBConfig(
+            x_handle.raw_display(),
+            glx_frame_buffer_config,
+        )
+    };
+    if visual_info.is_null() {
+        return Err(WindowError::NoMatchingFbConfig);
+    }
+    let visual_id = unsafe { (*visual_info).visualid as u32 };
+    let screen_num = unsafe { (*visual_info).screen as usize };
+    let depth = unsafe { (*visual_info).depth as u8 };
+
+    // Don't need this visual info anymore.
+    unsafe { xlib::XFree(visual_info as *mut c_void) };
+
+    let gl_context =
+        gl_utils::create_gl_context(x_handle.clone(), glx_frame_buffer_config, config)?;
+
+    Ok(GlxSetup {
+        x_handle: x_handle.clone(),
+        visual_id,
+        screen_num,
+        depth,
+        gl_context,
+    })
+}
+
+/// The window an event is about, for the events a `WindowContext` selects.
+pub fn event_window(ev: &xcb::GenericEvent) -> Option<u32> {
+    // All of these are casts of the raw event; each type only reads its own layout.
+    unsafe {
+        match ev.response_type() & !0x80 {
+            xcb::EXPOSE => Some(xcb::cast_event::<xcb::ExposeEvent>(ev).window()),
+            xcb::BUTTON_PRESS | xcb::BUTTON_RELEASE => {
+                Some(xcb::cast_event::<xcb::ButtonPressEvent>(ev).event())
+            }
+            xcb::MOTION_NOTIFY => Some(xcb::cast_event::<xcb::MotionNotifyEvent>(ev).event()),
+            xcb::ENTER_NOTIFY | xcb::LEAVE_NOTIFY => {
+                Some(xcb::cast_event::<xcb::EnterNotifyEvent>(ev).event())
+            }
+            xcb::KEY_PRESS | xcb::KEY_RELEASE => {
+                Some(xcb::cast_event::<xcb::KeyPressEvent>(ev).event())
+            }
+            xcb::FOCUS_IN | xcb::FOCUS_OUT => {
+                Some(xcb::cast_event::<xcb::FocusInEvent>(ev).event())
+            }
+            xcb::CONFIGURE_NOTIFY => {
+                Some(xcb::cast_event::<xcb::ConfigureNotifyEvent>(ev).window())
+            }
+            xcb::MAP_NOTIFY | xcb::UNMAP_NOTIFY => {
+                Some(xcb::cast_event::<xcb::MapNotifyEvent>(ev).window())
+            }
+            xcb::VISIBILITY_NOTIFY => {
+                Some(xcb::cast_event::<xcb::VisibilityNotifyEvent>(ev).window())
+            }
+            xcb::CLIENT_MESSAGE => Some(xcb::cast_event::<xcb::ClientMessageEvent>(ev).window()),
+            _ => None,
+        }
+    }
+}
+
+/// Add structure events (resizes, maps and unmaps) to the events we select on `window`, which
+/// isn't ours. The mask we set replaces the one our connection had before, and threaded windows
+/// share a connection: another editor may be watching the same window already.
+fn watch_structure(x_handle: &XHandle, window: u32) {
+    let cookie = xcb::get_window_attributes(x_handle.conn_ref(), window);
+    let event_mask = cookie.get_reply().map(|attributes| attributes.your_event_mask()).unwrap_or(0);
+    xcb::change_window_attributes(
+        x_handle.conn_ref(),
+        window,
+        &[(xcb::CW_EVENT_MASK, event_mask | xcb::EVENT_MASK_STRUCTURE_NOTIFY)],
+    );
+}
+
+/// Where `(x, y)` in `window` is, in root window coordinates.
+fn root_position(x_handle: &XHandle, window: u32, root: u32, x: i16, y: i16) -> (i32, i32) {
+    let cookie = xcb::translate_coordinates(x_handle.conn_ref(), window, root, x, y);
+    match cookie.get_reply() {
+        Ok(reply) => (reply.dst_x() as i32, reply.dst_y() as i32),
+        Err(_) => (0, 0),
+    }
+}
+
+/// The ancestor of `window` that's a direct child of the root window (the host's top-level
+/// window, or the window manager's frame around it).
+fn find_toplevel(x_handle: &XHandle, mut window: u32, root: u32) -> u32 {
+    loop {
+        match xcb::query_tree(x_handle.conn_ref(), window).get_reply() {
+            Ok(tree) if tree.parent() != root && tree.parent() != xcb::NONE => {
+                window = tree.parent();
+            }
+            _ => return window,
+        }
+    }
+}
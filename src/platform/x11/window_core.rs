@@ -0,0 +1,253 @@
+// The part of a window that doesn't talk to X: the `GuiState`, and what it's told when. Input in
+// logical units, resizes, scale changes, messages, timers, parameters, and when to draw under the
+// redraw policy. `WindowContext` drives it from X events, `MockWindow` from scripted ones.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use glow::HasContext;
+use log::*;
+
+use crate::gui_state::{Frame, GuiState, KeyEvent, Modifiers, MouseEvent};
+use crate::pixel_buffer::PixelBuffer;
+use crate::window::{EventLoopMode, RawWindow, WindowOptions};
+use super::frame_clock::FrameClock;
+use super::input;
+use super::proxy::{self, Shared};
+use super::scale;
+use super::state_guard::GuardedState;
+
+/// What a window whose `GuiState` panicked shows with `WindowOptions::panic_screen`.
+pub const PANIC_SCREEN_COLOR: [u8; 4] = [0x80, 0x10, 0x10, 0xff];
+
+pub struct WindowCore {
+    state: GuardedState,
+    shared: Arc<Shared>,
+    frame_clock: FrameClock,
+    // In pixels.
+    size: (u32, u32),
+    scale: f64,
+    logical_units: bool,
+    panic_screen: bool,
+    // Whether we're on the host's thread, where the `HostResizeCallback` may be called.
+    host_driven: bool,
+}
+
+impl WindowCore {
+    /// `size` is in pixels, and `scale` the scale factor the window starts out with.
+    pub fn new(
+        state: Box<dyn GuiState>,
+        shared: Arc<Shared>,
+        size: (u32, u32),
+        scale: f64,
+        options: &WindowOptions,
+    ) -> Self {
+        shared.set_scale_factor(scale);
+        Self {
+            state: GuardedState::new(state, shared.clone()),
+            shared,
+            frame_clock: FrameClock::new(options.redraw_policy),
+            size,
+            scale,
+            logical_units: options.logical_units,
+            panic_screen: options.panic_screen,
+            host_driven: options.event_loop == EventLoopMode::HostDriven,
+        }
+    }
+
+    pub fn shared(&self) -> &Arc<Shared> {
+        &self.shared
+    }
+
+    /// The window's size, in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Call `f` with the state; see `GuardedState::call()`.
+    pub fn call<R>(&mut self, f: impl FnOnce(&mut dyn GuiState) -> R) -> Option<R> {
+        self.state.call(f)
+    }
+
+    /// A press or release of X mouse button `button` at (`x`, `y`), in pixels.
+    pub fn button(
+        &mut self,
+        button: u8,
+        pressed: bool,
+        x: i32,
+        y: i32,
+        modifiers: Modifiers,
+        time: u32,
+    ) {
+        match input::mouse_button_event(button, pressed) {
+            Some(mouse_event) => self.pointer(mouse_event, x, y, modifiers, time),
+            None if pressed => info!("Unknown mouse button: {} ({}, {})", button, x, y),
+            None => {}
+        }
+    }
+
+    /// Any other mouse event (motion, enter and leave) at (`x`, `y`), in pixels.
+    pub fn pointer(
+        &mut self,
+        mouse_event: MouseEvent,
+        x: i32,
+        y: i32,
+        modifiers: Modifiers,
+        time: u32,
+    ) {
+        let (x, y) = self.to_logical_point(x, y);
+        self.state.call(|state| state.handle_mouse(mouse_event, x, y, modifiers, time));
+    }
+
+    pub fn key(&mut self, key_event: KeyEvent) {
+        self.state.call(|state| state.handle_keyboard(key_event));
+    }
+
+    /// The user asked to close the window; what that means is up to the state.
+    pub fn close_requested(&mut self) {
+        self.state.call(|state| state.close_requested());
+    }
+
+    /// The window is `size` pixels now. Returns whether that's a change.
+    pub fn resized(&mut self, size: (u32, u32)) -> bool {
+        // X also reports moves and restacking; ignore those.
+        if size == self.size {
+            return false;
+        }
+        self.size = size;
+        let (width, height) = self.to_logical_size(size);
+        self.state.call(|state| state.resized(width, height));
+        // X only sends EXPOSE for newly visible areas, so shrinking wouldn't redraw otherwise.
+        self.shared.request_redraw();
+        true
+    }
+
+    /// The window is on a monitor with scale factor `scale` now. With logical units, returns the
+    /// size in pixels to resize the window to, if the host agreed; see `ask_host_to_resize()`.
+    pub fn scale_changed(&mut self, scale: f64) -> Option<(u32, u32)> {
+        if (scale - self.scale).abs() < 0.01 {
+            return None;
+        }
+        info!("Scale factor changed from {} to {}", self.scale, scale);
+
+        let logical_size = self.to_logical_size(self.size);
+        self.scale = scale;
+        self.shared.set_scale_factor(scale);
+        let mut new_size = None;
+        if self.logical_units {
+            // Keep the logical size, which means a new size in pixels.
+            new_size = self.ask_host_to_resize(self.to_physical_size(logical_size));
+        }
+        self.state.call(|state| state.scale_factor_changed(scale));
+        self.shared.request_redraw();
+        new_size
+    }
+
+    /// The size in pixels a `WindowProxy` asked to resize to, if it did and the host agreed; see
+    /// `ask_host_to_resize()`.
+    pub fn take_resize_request(&mut self) -> Option<(u32, u32)> {
+        let size = self.to_physical_size(self.shared.take_resize_request()?);
+        self.ask_host_to_resize(size)
+    }
+
+    /// Returns `size` if the window should be resized to it now. The host is only asked on its
+    /// own thread; elsewhere, the request waits for the host's next `Window::pump_events()`,
+    /// which resizes the window if the host agrees.
+    fn ask_host_to_resize(&self, size: (u32, u32)) -> Option<(u32, u32)> {
+        if !self.host_driven && self.shared.has_host_resize() {
+            self.shared.defer_host_resize(size);
+            None
+        } else if self.shared.ask_host_to_resize(size) {
+            Some(size)
+        } else {
+            info!("Host refused to resize the editor to {:?}", size);
+            None
+        }
+    }
+
+    /// Deliver the messages posted through `WindowProxy`s, due timers and the changes from the
+    /// `ParameterQueue`. Returns whether to draw: if anything changed, anyone asked for it, or
+    /// the redraw policy wants a frame and the window is `visible`.
+    pub fn dispatch_pending(&mut self, visible: bool) -> bool {
+        let changed = self.state.dispatch_pending();
+        let frame_due = visible && self.frame_clock.is_due(self.shared.now());
+        self.shared.take_redraw_request() || changed || frame_due
+    }
+
+    /// How long the event loop may block waiting for events. `None` means forever.
+    pub fn wait_timeout(&self, visible: bool) -> Option<Duration> {
+        let next_frame = if visible {
+            self.frame_clock.time_until_next_frame(self.shared.now())
+        } else {
+            None
+        };
+        proxy::min_timeout(self.shared.wait_timeout(), next_frame)
+    }
+
+    /// Start a frame, unless there's nothing to draw: the state panicked, and there's no panic
+    /// screen to show instead.
+    pub fn start_frame(&mut self) -> Option<Frame> {
+        if self.state.is_poisoned() && !self.panic_screen {
+            return None;
+        }
+        Some(self.frame_clock.start_frame(self.shared.now()))
+    }
+
+    /// Draw a frame with the GL context current.
+    pub fn draw_gl(&mut self, gl: &glow::Context, frame: &Frame) {
+        if !self.state.is_poisoned() {
+            self.state.call(|state| state.draw(gl, frame));
+            return;
+        }
+        let [r, g, b, a] = PANIC_SCREEN_COLOR.map(|c| c as f32 / 255.0);
+        unsafe {
+            gl.clear_color(r, g, b, a);
+            gl.clear(glow::COLOR_BUFFER_BIT);
+        }
+    }
+
+    /// Draw a frame into `buffer`, which is the size of the window.
+    pub fn draw_software(&mut self, buffer: &mut PixelBuffer, frame: &Frame) {
+        if self.state.is_poisoned() {
+            buffer.fill(PANIC_SCREEN_COLOR);
+        } else {
+            self.state.call(|state| state.draw_software(buffer, frame));
+        }
+    }
+
+    pub fn draw_raw(&mut self, window: &RawWindow, frame: &Frame) {
+        // There's nothing to draw a panic screen with; the window keeps its background.
+        self.state.call(|state| state.draw_raw(window, frame));
+    }
+
+    pub fn into_state(self) -> Box<dyn GuiState> {
+        self.state.into_inner()
+    }
+
+    fn to_logical_point(&self, x: i32, y: i32) -> (i32, i32) {
+        if self.logical_units {
+            (
+                (x as f64 / self.scale).round() as i32,
+                (y as f64 / self.scale).round() as i32,
+            )
+        } else {
+            (x, y)
+        }
+    }
+
+    pub fn to_logical_size(&self, size: (u32, u32)) -> (u32, u32) {
+        if self.logical_units {
+            scale::to_logical(size, self.scale)
+        } else {
+            size
+        }
+    }
+
+    pub fn to_physical_size(&self, size: (u32, u32)) -> (u32, u32) {
+        if self.logical_units {
+            scale::to_physical(size, self.scale)
+        } else {
+            size
+        }
+    }
+}
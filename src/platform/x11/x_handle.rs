@@ -1,19 +1,50 @@
-use x11;
-use xcb;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Mutex;
+
 use log::*;
+use x11::xlib;
+
+use crate::error::{WindowError, XError};
+use crate::window::RawWindow;
+
+type XErrorHandler = unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int;
+
+/// The `trap_errors()` calls in progress, and the Xlib error handler we took over from while
+/// there are any.
+static ERROR_TRAPS: Mutex<ErrorTraps> = Mutex::new(ErrorTraps {
+    previous: None,
+    traps: Vec::new(),
+});
+
+struct ErrorTraps {
+    previous: Option<XErrorHandler>,
+    // (display, first serial, first error)
+    traps: Vec<(usize, c_ulong, Option<XError>)>,
+}
 
 pub struct XHandle {
     conn: xcb::Connection,
     screen_num: i32,
+    // Only one trap_errors() at a time per display.
+    trap_lock: Mutex<()>,
 }
 
 impl XHandle {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, WindowError> {
         info!("XHandle::new()");
 
-        let (conn, screen_num) = xcb::Connection::connect_with_xlib_display().unwrap();
+        let (conn, screen_num) = xcb::Connection::connect_with_xlib_display()
+            .map_err(|_| WindowError::NoDisplay)?;
+        // We read all events through XCB; don't let Xlib (e.g. inside XSync or GLX calls) pull
+        // them into its own queue where we'd never see them.
+        conn.set_event_queue_owner(xcb::base::EventQueueOwner::Xcb);
 
-        Self { conn, screen_num }
+        Ok(Self {
+            conn,
+            screen_num,
+            trap_lock: Mutex::new(()),
+        })
     }
 
     pub fn screen_num(&self) -> i32 {
@@ -27,12 +58,35 @@ impl XHandle {
     pub fn screen(
         &self,
         visual_info_screen: usize,
-    ) -> xcb::base::StructPtr<xcb::ffi::xproto::xcb_screen_t> {
+    ) -> xcb::base::StructPtr<'_, xcb::ffi::xproto::xcb_screen_t> {
         let setup = self.conn.get_setup();
         let screen = setup.roots().nth(visual_info_screen).unwrap();
         screen
     }
 
+    /// The depth of visual `visual_id` on our screen, if it's there.
+    #[cfg(feature = "egl")]
+    pub fn visual_depth(&self, visual_id: u32) -> Option<u8> {
+        let screen = self.screen(self.screen_num as usize);
+        for depth in screen.allowed_depths() {
+            if depth.visuals().any(|visual| visual.visual_id() == visual_id) {
+                return Some(depth.depth());
+            }
+        }
+        None
+    }
+
+    /// The raw handles for `window`, which has visual `visual_id`.
+    pub fn raw_window(&self, window: u32, visual_id: u32) -> RawWindow {
+        RawWindow {
+            xcb_connection: self.conn.get_raw_conn() as *mut c_void,
+            xlib_display: self.raw_display() as *mut c_void,
+            screen: self.screen_num,
+            window,
+            visual_id,
+        }
+    }
+
     pub fn generate_id(&self) -> u32 {
         self.conn.generate_id()
     }
@@ -41,33 +95,150 @@ impl XHandle {
         &self.conn
     }
 
-    pub fn make_cookie_atom(&self, only_if_exists: bool, name: &str) -> u32 {
+    pub fn make_cookie_atom(&self, only_if_exists: bool, name: &str) -> Result<u32, WindowError> {
         let cookie = xcb::intern_atom(&self.conn, only_if_exists, name);
-        if let Ok(reply) = cookie.get_reply() {
-            return reply.atom();
-        } else {
-            panic!("could not load atom for {}", name);
-        }
+        cookie
+            .get_reply()
+            .map(|reply| reply.atom())
+            .map_err(|_| WindowError::AtomFailed(name.to_string()))
     }
 
     pub fn flush(&self) {
         self.conn.flush();
     }
 
-    pub fn wait_for_event(&self) -> Option<xcb::Event<xcb::ffi::xcb_generic_event_t>> {
-        self.conn.wait_for_event()
+    /// Wait for the server to process a `_checked` request, and turn an error into ours.
+    pub fn check(&self, cookie: xcb::VoidCookie, request: &'static str) -> Result<(), WindowError> {
+        cookie.request_check().map_err(|error| {
+            // xcb_generic_error_t is what every error starts with.
+            let error = unsafe { &*(error.ptr as *const xcb::ffi::xcb_generic_error_t) };
+            WindowError::X(x_error(request, error))
+        })
     }
 
-    pub fn send_event(&self, window_id: u32, event: *const i8) {
+    /// Run `f`, which makes Xlib requests, and fail with the first X error they caused, if any.
+    /// The errors don't reach the process's Xlib error handler. Xlib only has the one handler
+    /// for the whole process, so ours is installed for the duration of the call and nothing
+    /// else; requests made through XCB should use `check()` instead.
+    pub fn trap_errors<R>(
+        &self,
+        request: &'static str,
+        f: impl FnOnce() -> R,
+    ) -> Result<R, WindowError> {
+        let _trap = self.trap_lock.lock().unwrap();
+        let dpy = self.raw_display();
+        let first_serial = unsafe { xlib::XNextRequest(dpy) };
+        {
+            let mut error_traps = ERROR_TRAPS.lock().unwrap();
+            if error_traps.traps.is_empty() {
+                error_traps.previous = unsafe { xlib::XSetErrorHandler(Some(error_handler)) };
+            }
+            error_traps.traps.push((dpy as usize, first_serial, None));
+        }
+
+        let result = f();
+        // Errors only show up once the server has seen the requests.
         unsafe {
-            xcb::ffi::xproto::xcb_send_event(
-                self.conn.get_raw_conn(),
-                false as u8,
-                window_id,
-                0,
-                event,
-            );
+            xlib::XSync(dpy, xlib::False);
+        }
+
+        let error = {
+            let mut error_traps = ERROR_TRAPS.lock().unwrap();
+            let traps = &mut error_traps.traps;
+            let index = traps.iter().position(|(display, ..)| *display == dpy as usize).unwrap();
+            let (.., error) = traps.swap_remove(index);
+            if traps.is_empty() {
+                restore_error_handler(error_traps.previous);
+            }
+            error
+        };
+        match error {
+            Some(mut error) => {
+                error.request = request;
+                Err(WindowError::X(error))
+            }
+            None => Ok(result),
+        }
+    }
+
+    /// Get the next event without blocking, if there is one. Errors from unchecked requests
+    /// arrive as events; they're logged and skipped.
+    pub fn poll_for_event(&self) -> Option<xcb::Event<xcb::ffi::xcb_generic_event_t>> {
+        loop {
+            let ev = self.conn.poll_for_event()?;
+            if ev.response_type() != 0 {
+                return Some(ev);
+            }
+            let error = unsafe { &*(ev.ptr as *const xcb::ffi::xcb_generic_error_t) };
+            warn!("Unexpected X error: {}", x_error("an unchecked request", error));
+        }
+    }
+
+    /// The connection's file descriptor, for waiting on it with poll() and friends.
+    pub fn connection_fd(&self) -> RawFd {
+        self.conn.as_raw_fd()
+    }
+
+    /// Whether the connection to the X server is broken (e.g. the server went away).
+    pub fn has_error(&self) -> bool {
+        self.conn.has_error().is_err()
+    }
+}
+
+fn x_error(request: &'static str, error: &xcb::ffi::xcb_generic_error_t) -> XError {
+    XError {
+        request,
+        error_code: error.error_code,
+        major_code: error.major_code,
+        minor_code: error.minor_code,
+        resource_id: error.resource_id,
+    }
+}
+
+/// Put back the handler that was there before the first trap. If someone replaced ours in the
+/// meantime, theirs stays; should they chain to ours, it still passes errors on to `previous`.
+fn restore_error_handler(previous: Option<XErrorHandler>) {
+    unsafe {
+        let current = xlib::XSetErrorHandler(previous);
+        let ours = error_handler as XErrorHandler as usize;
+        if current.map(|current| current as usize) != Some(ours) {
+            xlib::XSetErrorHandler(current);
+        }
+    }
+}
+
+/// Records errors on displays in `trap_errors()`, and passes everything else on to the handler
+/// that was there before us.
+unsafe extern "C" fn error_handler(
+    dpy: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    let event = &*event;
+    let previous = {
+        // A panic inside a trap doesn't make the list any less valid.
+        let mut error_traps = ERROR_TRAPS.lock().unwrap_or_else(|e| e.into_inner());
+        let trap = error_traps.traps.iter_mut().find(|(display, first_serial, _)| {
+            *display == dpy as usize && event.serial >= *first_serial
+        });
+        if let Some((.., trapped)) = trap {
+            if trapped.is_none() {
+                // trap_errors() fills in the request name.
+                *trapped = Some(XError {
+                    request: "",
+                    error_code: event.error_code,
+                    major_code: event.request_code,
+                    minor_code: event.minor_code as u16,
+                    resource_id: event.resourceid as u32,
+                });
+            }
+            return 0;
         }
+        error_traps.previous
+    };
+    // Not under the lock: the previous handler may well exit(), or make requests of its own.
+    match previous {
+        Some(previous) => previous(dpy, event as *const _ as *mut _),
+        None => 0,
     }
 }
 
@@ -0,0 +1,20 @@
+//! Unit-testing `GuiState`s without a display. Enabled with the `testing` cargo feature, e.g. as
+//! a dev-dependency.
+//!
+//! `MockWindow` implements `WindowImpl` entirely in memory, on the same event handling code as a
+//! real window. Create it with `WindowImpl::new()`, script events (`button()`, `pointer()`,
+//! `key()`, `resize()`, messages through its `proxy()`, ...), then drive it with `pump_events()`
+//! or `advance()`, which also moves its clock. Timers and redraw policies run on that clock, so
+//! every run sees the same frames.
+//!
+//! The state is boxed up inside the window, so have it share what the test should look at,
+//! e.g. through an `Arc<Mutex<...>>`. `draws()` records every draw call, and `buffer()` holds
+//! what the last software draw painted.
+//!
+//! There's no GL context, so only the software backend has its draws called. To test what's
+//! drawn, open the window with
+//! `WindowOptions { backend: Backend::Software, ..Default::default() }`. With the GL backends
+//! (the default), the frames still show up in `draws()`, but `GuiState::draw()` isn't called.
+
+pub use crate::platform::{DrawCall, MockWindow};
+pub use crate::window::WindowImpl;
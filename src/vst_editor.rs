@@ -0,0 +1,289 @@
+// A ready-made `vst::editor::Editor` on top of `Window`, so plugins don't each have to write the
+// same glue. Enabled with the `vst` cargo feature.
+
+use std::ffi::c_void;
+
+use log::*;
+use vst::api::ModifierKey;
+use vst::editor::{Editor, Key, KeyCode};
+use x11::keysym;
+
+use crate::gui_state::{GuiState, KeyEvent, Modifiers};
+use crate::window::{EventLoopMode, Window, WindowOptions};
+
+/// An `Editor` that opens a `Window` in `open()` and closes it in `close()`.
+///
+/// The window runs in host-driven mode: everything happens in the host's `idle()` calls, on the
+/// host's GUI thread. The `GuiState` is created by the factory the first time the editor is opened
+/// and then kept across close/reopen cycles.
+///
+/// The size is in the window's units: logical ones with `WindowOptions::logical_units`. The host
+/// always gets pixels from `Editor::size()`, at the scale factor of the last window opened (1.0
+/// before the first one).
+pub struct WindowEditor {
+    factory: Box<dyn FnMut() -> Box<dyn GuiState>>,
+    size: (u32, u32),
+    scale_factor: f64,
+    options: WindowOptions,
+    window: Option<Window>,
+    state: Option<Box<dyn GuiState>>,
+}
+
+impl WindowEditor {
+    pub fn new<F>(size: (u32, u32), factory: F) -> Self
+    where
+        F: FnMut() -> Box<dyn GuiState> + 'static,
+    {
+        Self::with_options(size, WindowOptions::default(), factory)
+    }
+
+    /// `options.event_loop` is ignored; the editor is always host-driven.
+    pub fn with_options<F>(size: (u32, u32), mut options: WindowOptions, factory: F) -> Self
+    where
+        F: FnMut() -> Box<dyn GuiState> + 'static,
+    {
+        options.event_loop = EventLoopMode::HostDriven;
+        Self {
+            factory: Box::new(factory),
+            size,
+            scale_factor: 1.0,
+            options,
+            window: None,
+            state: None,
+        }
+    }
+
+    /// The open window, if there is one.
+    pub fn window(&self) -> Option<&Window> {
+        self.window.as_ref()
+    }
+}
+
+impl Editor for WindowEditor {
+    fn size(&self) -> (i32, i32) {
+        let ((width, height), scale_factor) = match &self.window {
+            Some(window) => (window.size(), window.scale_factor()),
+            None => (self.size, self.scale_factor),
+        };
+        if self.options.logical_units {
+            let to_pixels = |length: u32| (length as f64 * scale_factor).round() as i32;
+            (to_pixels(width), to_pixels(height))
+        } else {
+            (width as i32, height as i32)
+        }
+    }
+
+    fn position(&self) -> (i32, i32) {
+        (0, 0)
+    }
+
+    fn idle(&mut self) {
+        if let Some(window) = self.window.as_mut() {
+            window.idle();
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(window) = self.window.take() {
+            self.size = window.size();
+            self.scale_factor = window.scale_factor();
+            // Keep the GuiState around for the next open().
+            self.state = window.into_state();
+        }
+    }
+
+    fn open(&mut self, parent: *mut c_void) -> bool {
+        // Some hosts open the editor again without closing it first.
+        self.close();
+
+        if self.state.is_none() {
+            self.state = Some((self.factory)());
+        }
+        // If this fails, the GuiState stays in `self.state` for the next try.
+        match Window::open(&mut self.state, parent, self.size, self.options.clone()) {
+            Ok(window) => {
+                self.window = Some(window);
+                true
+            }
+            Err(e) => {
+                error!("Could not open editor window: {}", e);
+                false
+            }
+        }
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.window.is_some()
+    }
+
+    // We can't tell whether the GuiState actually used a key, so we never claim it and the host
+    // still gets to act on it.
+    fn key_up(&mut self, keycode: KeyCode) -> bool {
+        if let Some(window) = self.window.as_mut() {
+            window.handle_host_key(key_event_from_vst(keycode, false));
+        }
+        false
+    }
+
+    fn key_down(&mut self, keycode: KeyCode) -> bool {
+        if let Some(window) = self.window.as_mut() {
+            window.handle_host_key(key_event_from_vst(keycode, true));
+        }
+        false
+    }
+}
+
+fn key_event_from_vst(keycode: KeyCode, pressed: bool) -> KeyEvent {
+    let modifier = ModifierKey::from_bits_truncate(keycode.modifier);
+    let text = Some(keycode.character)
+        .filter(|c| *c != '\0' && !c.is_control())
+        .map(|c| c.to_string());
+    let keysym = match keycode.key {
+        Key::None => keysym_from_char(keycode.character),
+        key => keysym_from_vst(key),
+    };
+
+    KeyEvent {
+        pressed,
+        // The host doesn't tell us the hardware keycode.
+        keycode: 0,
+        keysym,
+        text,
+        modifiers: Modifiers {
+            shift: modifier.contains(ModifierKey::SHIFT),
+            ctrl: modifier.contains(ModifierKey::CONTROL),
+            alt: modifier.contains(ModifierKey::ALT),
+            super_key: modifier.contains(ModifierKey::COMMAND),
+        },
+        repeat: false,
+        time: 0,
+    }
+}
+
+/// The keysym for typing `c`. Latin-1 keysyms are the code points themselves; everything else
+/// uses X's Unicode keysyms.
+fn keysym_from_char(c: char) -> u32 {
+    match c as u32 {
+        code_point @ 0..=0xff => code_point,
+        code_point => 0x0100_0000 | code_point,
+    }
+}
+
+fn keysym_from_vst(key: Key) -> u32 {
+    match key {
+        Key::None => 0, // NoSymbol
+        Key::Back => keysym::XK_BackSpace,
+        Key::Tab => keysym::XK_Tab,
+        Key::Clear => keysym::XK_Clear,
+        Key::Return => keysym::XK_Return,
+        Key::Pause => keysym::XK_Pause,
+        Key::Escape => keysym::XK_Escape,
+        Key::Space => keysym::XK_space,
+        Key::Next => keysym::XK_Next,
+        Key::End => keysym::XK_End,
+        Key::Home => keysym::XK_Home,
+        Key::Left => keysym::XK_Left,
+        Key::Up => keysym::XK_Up,
+        Key::Right => keysym::XK_Right,
+        Key::Down => keysym::XK_Down,
+        Key::PageUp => keysym::XK_Page_Up,
+        Key::PageDown => keysym::XK_Page_Down,
+        Key::Select => keysym::XK_Select,
+        Key::Print => keysym::XK_Print,
+        Key::Enter => keysym::XK_KP_Enter,
+        Key::Snapshot => keysym::XK_Print,
+        Key::Insert => keysym::XK_Insert,
+        Key::Delete => keysym::XK_Delete,
+        Key::Help => keysym::XK_Help,
+        Key::Numpad0 => keysym::XK_KP_0,
+        Key::Numpad1 => keysym::XK_KP_1,
+        Key::Numpad2 => keysym::XK_KP_2,
+        Key::Numpad3 => keysym::XK_KP_3,
+        Key::Numpad4 => keysym::XK_KP_4,
+        Key::Numpad5 => keysym::XK_KP_5,
+        Key::Numpad6 => keysym::XK_KP_6,
+        Key::Numpad7 => keysym::XK_KP_7,
+        Key::Numpad8 => keysym::XK_KP_8,
+        Key::Numpad9 => keysym::XK_KP_9,
+        Key::Multiply => keysym::XK_KP_Multiply,
+        Key::Add => keysym::XK_KP_Add,
+        Key::Separator => keysym::XK_KP_Separator,
+        Key::Subtract => keysym::XK_KP_Subtract,
+        Key::Decimal => keysym::XK_KP_Decimal,
+        Key::Divide => keysym::XK_KP_Divide,
+        Key::F1 => keysym::XK_F1,
+        Key::F2 => keysym::XK_F2,
+        Key::F3 => keysym::XK_F3,
+        Key::F4 => keysym::XK_F4,
+        Key::F5 => keysym::XK_F5,
+        Key::F6 => keysym::XK_F6,
+        Key::F7 => keysym::XK_F7,
+        Key::F8 => keysym::XK_F8,
+        Key::F9 => keysym::XK_F9,
+        Key::F10 => keysym::XK_F10,
+        Key::F11 => keysym::XK_F11,
+        Key::F12 => keysym::XK_F12,
+        Key::Numlock => keysym::XK_Num_Lock,
+        Key::Scroll => keysym::XK_Scroll_Lock,
+        Key::Shift => keysym::XK_Shift_L,
+        Key::Control => keysym::XK_Control_L,
+        Key::Alt => keysym::XK_Alt_L,
+        Key::Equals => keysym::XK_KP_Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_key_translation() {
+        let key_event = key_event_from_vst(
+            KeyCode { character: 'a', key: Key::None, modifier: ModifierKey::SHIFT.bits() },
+            true,
+        );
+        assert_eq!(key_event.keysym, keysym::XK_a);
+        assert_eq!(key_event.text.as_deref(), Some("a"));
+        assert!(key_event.modifiers.shift);
+
+        let key_event = key_event_from_vst(
+            KeyCode { character: '\r', key: Key::Return, modifier: 0 },
+            false,
+        );
+        assert_eq!(key_event.keysym, keysym::XK_Return);
+        assert_eq!(key_event.text, None);
+
+        let key_event =
+            key_event_from_vst(KeyCode { character: 'ж', key: Key::None, modifier: 0 }, true);
+        assert_eq!(key_event.keysym, 0x0100_0436);
+        assert_eq!(key_event.text.as_deref(), Some("ж"));
+    }
+
+    #[test]
+    fn host_gets_pixels() {
+        struct NoState;
+        impl GuiState for NoState {
+            fn draw(&mut self, _gl: &glow::Context, _frame: &crate::gui_state::Frame) {}
+            fn handle_mouse(
+                &mut self,
+                _: crate::gui_state::MouseEvent,
+                _: i32,
+                _: i32,
+                _: Modifiers,
+                _: u32,
+            ) {
+            }
+        }
+
+        let options = WindowOptions { logical_units: true, ..Default::default() };
+        let mut editor = WindowEditor::with_options((300, 200), options, || Box::new(NoState));
+        assert_eq!(editor.size(), (300, 200));
+        // As if the last window was on a HiDPI monitor.
+        editor.scale_factor = 1.5;
+        assert_eq!(editor.size(), (450, 300));
+
+        let mut editor = WindowEditor::new((300, 200), || Box::new(NoState));
+        editor.scale_factor = 1.5;
+        assert_eq!(editor.size(), (300, 200));
+    }
+}
@@ -1,22 +1,571 @@
+use std::any::Any;
 use std::ffi::c_void;
+use std::fmt;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::platform::PlatformWindow;
-use crate::gui_state::{GuiState, MouseEvent};
+use raw_window_handle::{
+    HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+    XcbDisplayHandle, XcbWindowHandle, XlibDisplayHandle, XlibWindowHandle,
+};
+
+use crate::error::WindowError;
+use crate::platform::{PlatformProxy, PlatformWindow};
+use crate::gui_state::{GuiState, KeyEvent};
+use crate::parameters::ParameterQueue;
+
+/// How a window's events get handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventLoopMode {
+    /// Events are handled on a GUI thread, which also calls into the `GuiState`. All threaded
+    /// windows in the process share one thread and one X connection; the thread starts with the
+    /// first window and stops when the last one closes.
+    #[default]
+    Threaded,
+    /// No thread is spawned. The host has to call `Window::pump_events()` (or `Window::idle()`)
+    /// regularly, e.g. from `effEditIdle` or when `Window::connection_fd()` or
+    /// `Window::wake_fd()` becomes readable. All `GuiState` callbacks then happen on the host's
+    /// thread.
+    HostDriven,
+}
+
+/// What draws the window's contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Use OpenGL if we can, and fall back to software rendering if creating the GL context
+    /// fails (broken drivers, VMs, remote X). Implement both `GuiState::draw()` and
+    /// `GuiState::draw_software()` to use this; otherwise the fallback is a blank window.
+    Auto,
+    /// OpenGL only. Creating the window fails if we can't get a GL context.
+    #[default]
+    OpenGl,
+    /// No OpenGL at all: `GuiState::draw_software()` draws into a `PixelBuffer`, which is copied
+    /// to the window through shared memory (MIT-SHM) if possible.
+    Software,
+    /// OpenGL or OpenGL ES through EGL instead of GLX. `GuiState::draw()` is called just like
+    /// with `OpenGl`. Needs the `egl` cargo feature; without it, creating the window fails.
+    Egl(EglApi),
+    /// Bring your own renderer (wgpu, femtovg, softbuffer, glutin, ...): no GL context is
+    /// created, and `GuiState::draw_raw()` is called instead of `draw()`, with the `RawWindow`
+    /// to render into. The window uses the screen's default visual.
+    None,
+}
+
+/// A window's raw platform handles, for rendering into it with a library of your own. See
+/// `Backend::None`.
+///
+/// The trait impls hand out the Xlib handles, which everything (including glutin's GLX) takes;
+/// `xcb_window_handle()` and `xcb_display_handle()` give the XCB ones. They're valid until the
+/// window is closed. The display connection may be shared with other windows, so don't close it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawWindow {
+    pub(crate) xcb_connection: *mut c_void,
+    pub(crate) xlib_display: *mut c_void,
+    pub(crate) screen: i32,
+    pub(crate) window: u32,
+    pub(crate) visual_id: u32,
+}
+
+impl RawWindow {
+    pub fn xlib_window_handle(&self) -> RawWindowHandle {
+        let mut handle = XlibWindowHandle::empty();
+        handle.window = self.window.into();
+        handle.visual_id = self.visual_id.into();
+        RawWindowHandle::Xlib(handle)
+    }
+
+    pub fn xlib_display_handle(&self) -> RawDisplayHandle {
+        let mut handle = XlibDisplayHandle::empty();
+        handle.display = self.xlib_display;
+        handle.screen = self.screen;
+        RawDisplayHandle::Xlib(handle)
+    }
+
+    pub fn xcb_window_handle(&self) -> RawWindowHandle {
+        let mut handle = XcbWindowHandle::empty();
+        handle.window = self.window;
+        handle.visual_id = self.visual_id;
+        RawWindowHandle::Xcb(handle)
+    }
+
+    pub fn xcb_display_handle(&self) -> RawDisplayHandle {
+        let mut handle = XcbDisplayHandle::empty();
+        handle.connection = self.xcb_connection;
+        handle.screen = self.screen;
+        RawDisplayHandle::Xcb(handle)
+    }
+}
+
+unsafe impl HasRawWindowHandle for RawWindow {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.xlib_window_handle()
+    }
+}
+
+unsafe impl HasRawDisplayHandle for RawWindow {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        self.xlib_display_handle()
+    }
+}
+
+/// Which API an EGL context is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EglApi {
+    /// Desktop OpenGL, configured by `WindowOptions::gl_config` like the GLX backend.
+    #[default]
+    OpenGl,
+    /// OpenGL ES 2.0.
+    Gles2,
+    /// OpenGL ES 3.0.
+    Gles3,
+}
+
+/// Which OpenGL profile to ask for. Only matters for OpenGL 3.2 and later; older versions don't
+/// have profiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlProfile {
+    /// Only the non-deprecated functionality.
+    Core,
+    /// Everything, including the fixed-function pipeline.
+    #[default]
+    Compatibility,
+}
+
+/// Whether buffer swaps wait for the display's vertical blank.
+///
+/// Blocking on vblank caps the editor at the display's refresh rate, but hosts that show many
+/// editors at once may be better off with `Off`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Vsync {
+    /// Leave it to the driver and its settings (e.g. `vblank_mode` for Mesa).
+    #[default]
+    DriverDefault,
+    Off,
+    On,
+    /// Wait for vblank, unless the frame is late; then swap right away and tear. Needs
+    /// GLX_EXT_swap_control_tear, and is the same as `On` without it (and with EGL).
+    Adaptive,
+}
+
+/// What to do, besides logging it, when a debug context reports a high severity message. See
+/// `GlConfig::debug`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlDebugBreak {
+    #[default]
+    Off,
+    /// Panic once the `GuiState::draw()` that caused the message has returned.
+    Panic,
+    /// Raise `SIGTRAP` inside the offending GL call, so a debugger stops right there. Without a
+    /// debugger attached, this kills the process (and the host with it).
+    Trap,
+}
+
+/// The OpenGL context and framebuffer to create. The default is an OpenGL 3.0 compatibility
+/// context with a 24 bit depth buffer and an 8 bit stencil buffer.
+///
+/// With `Backend::Egl(EglApi::Gles2)` or `Gles3`, only the framebuffer fields (`samples`, `srgb`,
+/// `depth_bits` and `stencil_bits`) are used; the ES version comes from the `EglApi`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlConfig {
+    /// The OpenGL version to ask for, as (major, minor). The driver may give you a later one.
+    pub version: (u8, u8),
+    pub profile: GlProfile,
+    /// Remove the functionality deprecated in the requested version. Needed for core profiles on
+    /// macOS, so some code sets it everywhere; it's harmless on X11.
+    pub forward_compatible: bool,
+    /// Create a debug context. If it supports KHR_debug or ARB_debug_output, its messages go
+    /// to the `log` crate: high severity as errors, medium as warnings, low as info and
+    /// notifications as debug.
+    pub debug: bool,
+    /// What else to do on high severity debug messages, e.g.
+    /// `if cfg!(debug_assertions) { GlDebugBreak::Panic } else { GlDebugBreak::Off }`.
+    pub debug_break: GlDebugBreak,
+    /// Samples per pixel for multisampling (MSAA). 0 turns multisampling off.
+    pub samples: u8,
+    /// Ask for an sRGB-capable framebuffer, so `GL_FRAMEBUFFER_SRGB` can be enabled.
+    pub srgb: bool,
+    pub depth_bits: u8,
+    pub stencil_bits: u8,
+    /// Set through GLX_EXT_swap_control, GLX_MESA_swap_control or GLX_SGI_swap_control, or
+    /// `eglSwapInterval()`. See `Window::swap_interval()` for what you actually got.
+    pub vsync: Vsync,
+    /// Versions to try, in order, if the driver can't create a context for `version`.
+    pub fallback_versions: Vec<(u8, u8)>,
+}
+
+impl Default for GlConfig {
+    fn default() -> Self {
+        Self {
+            version: (3, 0),
+            profile: GlProfile::Compatibility,
+            forward_compatible: false,
+            debug: false,
+            debug_break: GlDebugBreak::Off,
+            samples: 0,
+            srgb: false,
+            depth_bits: 24,
+            stencil_bits: 8,
+            vsync: Vsync::DriverDefault,
+            fallback_versions: Vec::new(),
+        }
+    }
+}
+
+impl GlConfig {
+    /// `version` followed by `fallback_versions`.
+    pub fn versions(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        std::iter::once(self.version).chain(self.fallback_versions.iter().cloned())
+    }
+}
+
+/// When the window gets redrawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedrawPolicy {
+    /// Only when something asks for it: an expose event, `Window::invalidate()`, a resize, a
+    /// parameter change.
+    #[default]
+    OnDemand,
+    /// Redraw again as soon as the last frame is done. With vsync on, that's once per vblank;
+    /// without it, as fast as the GPU goes.
+    Continuous,
+    /// Redraw continuously, but at most this many frames per second.
+    MaxFps(u32),
+}
+
+/// Options for creating a window. Use `..Default::default()` for the ones you don't care about.
+#[derive(Debug, Clone, Default)]
+pub struct WindowOptions {
+    pub event_loop: EventLoopMode,
+    pub backend: Backend,
+    /// Parameter values and meter levels from the audio thread. When set, the window checks the
+    /// queue about 60 times a second and redraws when something changed.
+    pub parameters: Option<Arc<ParameterQueue>>,
+    /// Keep the window the same size as its parent, e.g. when the host resizes the editor frame.
+    pub follow_parent: bool,
+    /// Take the keyboard focus when the window is clicked, so key events reach the `GuiState`
+    /// directly rather than through `Window::handle_host_key()`. Off by default: it takes the
+    /// host's shortcuts (like the space bar for play) away for as long as the editor has it.
+    pub take_focus: bool,
+    /// How to ask the host for a different editor size. See `Window::request_resize()`.
+    pub host_resize: Option<HostResizeCallback>,
+    /// Work in logical units instead of pixels: sizes passed to and returned by `Window`, mouse
+    /// coordinates and `GuiState::resized()` are all divided by the scale factor. The X window
+    /// and the GL viewport are still in pixels. When the scale factor changes, the window is
+    /// resized to keep its logical size.
+    pub logical_units: bool,
+    /// The OpenGL context and framebuffer to ask for. Ignored by the software backend.
+    pub gl_config: GlConfig,
+    /// Whether to keep redrawing for animations. Continuous redrawing pauses while the window is
+    /// unmapped or fully obscured. In `EventLoopMode::HostDriven`, frames can only happen when
+    /// the host calls `Window::pump_events()`.
+    pub redraw_policy: RedrawPolicy,
+    /// If a `GuiState` callback panics, fill the window with dark red instead of leaving whatever
+    /// was drawn last. See `Window::panic_message()`.
+    pub panic_screen: bool,
+}
+
+/// Asks the host to resize the editor, e.g. by sending `audioMasterSizeWindow`. Gets the new
+/// size (width, height) in pixels and returns whether the host agreed.
+///
+/// It's only called on the host's thread, where VST hosts expect `audioMasterSizeWindow`: from
+/// `Window::request_resize()`, or from `Window::pump_events()` for resizes the window itself
+/// asks for (`WindowProxy::request_resize()`, and scale changes with logical units). In
+/// host-driven mode those happen during `pump_events()` anyway; in threaded mode they wait for
+/// the next one, so call `Window::idle()` from `effEditIdle` even then.
+#[derive(Clone)]
+pub struct HostResizeCallback(Arc<dyn Fn(u32, u32) -> bool + Send + Sync>);
+
+impl HostResizeCallback {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(u32, u32) -> bool + Send + Sync + 'static,
+    {
+        HostResizeCallback(Arc::new(callback))
+    }
+
+    pub fn call(&self, size: (u32, u32)) -> bool {
+        (self.0)(size.0, size.1)
+    }
+}
+
+impl fmt::Debug for HostResizeCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HostResizeCallback")
+    }
+}
 
 pub struct Window {
     platform_window: Box<dyn WindowImpl>,
 }
 
 impl Window {
-    pub fn new(state: Box<dyn GuiState>, parent: *mut c_void, size: (u32, u32)) -> Self {
-        Self {
-            platform_window: Box::new(PlatformWindow::new(state, parent, size)),
+    pub fn new(
+        state: Box<dyn GuiState>,
+        parent: *mut c_void,
+        size: (u32, u32),
+    ) -> Result<Self, WindowError> {
+        Self::with_options(state, parent, size, WindowOptions::default())
+    }
+
+    pub fn with_options(
+        state: Box<dyn GuiState>,
+        parent: *mut c_void,
+        size: (u32, u32),
+        options: WindowOptions,
+    ) -> Result<Self, WindowError> {
+        Self::open(&mut Some(state), parent, size, options)
+    }
+
+    /// Like `with_options()`, but leaves the `GuiState` in `state` if the window couldn't be
+    /// created, so it can be used again.
+    pub(crate) fn open(
+        state: &mut Option<Box<dyn GuiState>>,
+        parent: *mut c_void,
+        size: (u32, u32),
+        options: WindowOptions,
+    ) -> Result<Self, WindowError> {
+        Ok(Self {
+            platform_window: Box::new(PlatformWindow::open(state, parent, size, options)?),
+        })
+    }
+
+    /// Resize the window to `size` (width, height), in pixels. This doesn't tell the host; use
+    /// `request_resize()` if the editor's frame should change size too.
+    pub fn resize(&self, size: (u32, u32)) {
+        self.platform_window.resize(size);
+    }
+
+    /// Ask the host to resize the editor through `WindowOptions::host_resize`, and resize the
+    /// window if it agrees. Without a `host_resize` callback, only the window is resized.
+    /// Returns `false` if the host refused.
+    pub fn request_resize(&self, size: (u32, u32)) -> bool {
+        self.platform_window.request_resize(size)
+    }
+
+    /// Map (display) the window.
+    pub fn show(&self) {
+        self.platform_window.show();
+    }
+
+    /// Unmap (hide) the window. The GL context and event thread are kept alive.
+    pub fn hide(&self) {
+        self.platform_window.hide();
+    }
+
+    /// Ask for the window to be redrawn. `GuiState::draw()` will be called on the GUI thread.
+    pub fn invalidate(&self) {
+        self.platform_window.invalidate();
+    }
+
+    /// Call `GuiState::timer(id)` once, `delay` from now, on the thread that handles the
+    /// window's events. Setting a timer with the id of one that's still pending replaces it,
+    /// which makes debouncing easy.
+    pub fn set_timer(&self, id: u64, delay: Duration) {
+        self.platform_window.set_timer(id, delay, false);
+    }
+
+    /// Like `set_timer()`, but keeps firing every `interval` (1ms at the least) until cancelled.
+    pub fn set_repeating_timer(&self, id: u64, interval: Duration) {
+        self.platform_window.set_timer(id, interval, true);
+    }
+
+    /// Stop timer `id`. Does nothing if there is no such timer.
+    pub fn cancel_timer(&self, id: u64) {
+        self.platform_window.cancel_timer(id);
+    }
+
+    /// The current size of the window (width, height), in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        self.platform_window.size()
+    }
+
+    /// The backend the window ended up using; never `Backend::Auto`.
+    pub fn backend(&self) -> Backend {
+        self.platform_window.backend()
+    }
+
+    /// The window's current scale factor (1.0 means 96 DPI). See
+    /// `GuiState::scale_factor_changed()`.
+    pub fn scale_factor(&self) -> f64 {
+        self.platform_window.scale_factor()
+    }
+
+    /// The swap interval in effect: 0 for no vsync, 1 for every vblank, -1 for adaptive vsync.
+    /// `None` if the window doesn't use OpenGL, or the driver doesn't say.
+    pub fn swap_interval(&self) -> Option<i32> {
+        self.platform_window.swap_interval()
+    }
+
+    /// Why the `GuiState` stopped working, if it did. When one of its callbacks panics, the panic
+    /// is caught, the window stays open, and the state isn't called again (not even
+    /// `gl_destroy()`). `into_state()` still hands it back.
+    pub fn panic_message(&self) -> Option<String> {
+        self.platform_window.panic_message()
+    }
+
+    /// A handle for talking to the window from other threads (e.g. the host's or the audio
+    /// thread's side of the plugin).
+    pub fn proxy(&self) -> WindowProxy {
+        WindowProxy {
+            platform_proxy: self.platform_window.proxy(),
         }
     }
+
+    /// The platform's ID for this window (the XID on X11).
+    pub fn window_id(&self) -> u32 {
+        self.platform_window.window_id()
+    }
+
+    /// The window's raw handles. `Window` implements the `raw-window-handle` traits too.
+    pub fn raw_window(&self) -> RawWindow {
+        self.platform_window.raw_window()
+    }
+
+    /// In host-driven mode, the file descriptor of the display connection. Register it with the
+    /// host's run loop, together with `wake_fd()`, and call `pump_events()` when either becomes
+    /// readable. `None` in threaded mode.
+    pub fn connection_fd(&self) -> Option<RawFd> {
+        self.platform_window.connection_fd()
+    }
+
+    /// In host-driven mode, a file descriptor that becomes readable when a `WindowProxy` posts a
+    /// message, asks for a redraw or a resize, or sets a timer. These don't go through the
+    /// display connection, so watching `connection_fd()` alone misses them until the next
+    /// `pump_events()`, which also makes it unreadable again. `None` in threaded mode.
+    pub fn wake_fd(&self) -> Option<RawFd> {
+        self.platform_window.wake_fd()
+    }
+
+    /// In host-driven mode, handle (and draw) everything that's pending, without blocking.
+    /// In threaded mode, only asks the host for the resizes the window is waiting on; see
+    /// `HostResizeCallback`.
+    pub fn pump_events(&mut self) {
+        self.platform_window.pump_events();
+    }
+
+    /// In host-driven mode, how long the host may wait before calling `pump_events()` again, at
+    /// most: until the next timer is due, the `ParameterQueue` needs checking, or the next frame
+    /// under `RedrawPolicy::Continuous`. A host that only calls `pump_events()` when the fds
+    /// become readable has to arm a timer with this after each call, or those never happen.
+    /// `None` means there's nothing to wait for, and always in threaded mode.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.platform_window.next_timeout()
+    }
+
+    /// Same as `pump_events()`; call it from `effEditIdle`.
+    pub fn idle(&mut self) {
+        self.pump_events();
+    }
+
+    /// Pass on a key event the host sent us (e.g. through `effEditKeyDown`) to the `GuiState`.
+    /// Only delivered in host-driven mode, where the `GuiState` lives on the host's thread.
+    pub fn handle_host_key(&mut self, key_event: KeyEvent) {
+        self.platform_window.handle_host_key(key_event);
+    }
+
+    /// Close the window and get the `GuiState` back, so it can be reused for the next window.
+    pub fn into_state(mut self) -> Option<Box<dyn GuiState>> {
+        self.platform_window.close()
+    }
+}
+
+unsafe impl HasRawWindowHandle for Window {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.raw_window().raw_window_handle()
+    }
+}
+
+unsafe impl HasRawDisplayHandle for Window {
+    fn raw_display_handle(&self) -> RawDisplayHandle {
+        self.raw_window().raw_display_handle()
+    }
+}
+
+/// A cloneable, `Send` handle to a `Window` that can be used from any thread.
+///
+/// Outliving the window is fine: once it's closed, everything sent through the proxy is dropped
+/// and the methods return `false`.
+#[derive(Clone)]
+pub struct WindowProxy {
+    platform_proxy: PlatformProxy,
+}
+
+impl WindowProxy {
+    /// Send a message to the `GuiState`. It's delivered to `GuiState::handle_message()` on the
+    /// thread that handles the window's events (in host-driven mode: during the next
+    /// `pump_events()`). Returns `false` if the window has been closed.
+    pub fn post_message<M: Any + Send>(&self, message: M) -> bool {
+        self.platform_proxy.post_message(Box::new(message))
+    }
+
+    /// Ask for the window to be redrawn. Requests are coalesced: however many come in, the next
+    /// pass through the event loop draws once. Returns `false` if the window has been closed.
+    pub fn request_redraw(&self) -> bool {
+        self.platform_proxy.request_redraw()
+    }
+
+    /// Like `Window::request_resize()`, but happens asynchronously on the thread that handles the
+    /// window's events. With a `HostResizeCallback` in threaded mode, it waits for the host's
+    /// next `Window::pump_events()` instead. `GuiState::resized()` is called once the window has
+    /// its new size. Returns `false` if the window has been closed.
+    pub fn request_resize(&self, size: (u32, u32)) -> bool {
+        self.platform_proxy.request_resize(size)
+    }
+
+    /// See `Window::set_timer()`. Returns `false` if the window has been closed.
+    pub fn set_timer(&self, id: u64, delay: Duration) -> bool {
+        self.platform_proxy.set_timer(id, delay, false)
+    }
+
+    /// See `Window::set_repeating_timer()`. Returns `false` if the window has been closed.
+    pub fn set_repeating_timer(&self, id: u64, interval: Duration) -> bool {
+        self.platform_proxy.set_timer(id, interval, true)
+    }
+
+    /// See `Window::cancel_timer()`. Returns `false` if the window has been closed.
+    pub fn cancel_timer(&self, id: u64) -> bool {
+        self.platform_proxy.cancel_timer(id)
+    }
+
+    /// Whether the window this proxy belongs to has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.platform_proxy.is_closed()
+    }
 }
 
 // TODO: Do I need to specify Drop here, or is it sufficient to just implement Drop for each WindowImpl if it needs it?
 pub trait WindowImpl {
-    fn new(state: Box<dyn GuiState>, parent: *mut c_void, size: (u32, u32)) -> PlatformWindow
+    fn new(
+        state: Box<dyn GuiState>,
+        parent: *mut c_void,
+        size: (u32, u32),
+        options: WindowOptions,
+    ) -> Result<Self, WindowError>
     where Self: Sized;
-}
\ No newline at end of file
+
+    fn resize(&self, size: (u32, u32));
+    fn request_resize(&self, size: (u32, u32)) -> bool;
+    fn show(&self);
+    fn hide(&self);
+    fn invalidate(&self);
+    fn set_timer(&self, id: u64, delay: Duration, repeat: bool);
+    fn cancel_timer(&self, id: u64);
+    fn size(&self) -> (u32, u32);
+    fn window_id(&self) -> u32;
+    fn raw_window(&self) -> RawWindow;
+    fn backend(&self) -> Backend;
+    fn scale_factor(&self) -> f64;
+    fn swap_interval(&self) -> Option<i32>;
+    fn panic_message(&self) -> Option<String>;
+    fn proxy(&self) -> PlatformProxy;
+    fn connection_fd(&self) -> Option<RawFd>;
+    fn wake_fd(&self) -> Option<RawFd>;
+    fn next_timeout(&self) -> Option<Duration>;
+    fn pump_events(&mut self);
+    fn handle_host_key(&mut self, key_event: KeyEvent);
+
+    /// Destroy the window and return its `GuiState`. Must be safe to call more than once (e.g.
+    /// again from `Drop`); later calls return `None`.
+    fn close(&mut self) -> Option<Box<dyn GuiState>>;
+}